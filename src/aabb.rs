@@ -1,7 +1,16 @@
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{
+    __m256d, _CMP_NLE_UQ, _mm256_cmp_pd, _mm256_max_pd, _mm256_min_pd, _mm256_movemask_pd,
+    _mm256_mul_pd, _mm256_set1_pd, _mm256_sub_pd,
+};
+
+#[cfg(target_arch = "x86_64")]
+use crate::packet;
 use crate::{
     interval::Interval,
+    packet::{PACKET_WIDTH, RayPacket},
     ray::Ray,
-    vec3::Point,
+    vec3::{Point, Vec3},
 };
 
 #[derive(Debug, Clone, Default)]
@@ -12,6 +21,12 @@ pub struct AABB {
 }
 
 impl AABB {
+    pub const EMPTY: AABB = AABB {
+        x: Interval::EMPTY,
+        y: Interval::EMPTY,
+        z: Interval::EMPTY,
+    };
+
     pub fn new(x: Interval, y: Interval, z: Interval) -> Self {
         AABB { x, y, z }
     }
@@ -32,6 +47,51 @@ impl AABB {
         AABB { x, y, z }
     }
 
+    /// Grows the box by `delta` on every side.
+    pub fn pad(&mut self, delta: f64) {
+        self.x = self.x.expand(2.0 * delta);
+        self.y = self.y.expand(2.0 * delta);
+        self.z = self.z.expand(2.0 * delta);
+    }
+
+    pub fn diagonal(&self) -> Vec3 {
+        Vec3::new(self.x.size(), self.y.size(), self.z.size())
+    }
+
+    /// Adjusts the box so that no side is narrower than some delta, flat
+    /// primitives would otherwise never be hit.
+    pub fn pad_to_minimums(&mut self) {
+        let delta = 0.0001;
+        if self.x.size() < delta {
+            self.x = self.x.expand(delta);
+        }
+        if self.y.size() < delta {
+            self.y = self.y.expand(delta);
+        }
+        if self.z.size() < delta {
+            self.z = self.z.expand(delta);
+        }
+    }
+
+    pub fn axis(&self, n: usize) -> &Interval {
+        match n {
+            1 => &self.y,
+            2 => &self.z,
+            _ => &self.x,
+        }
+    }
+
+    /// Returns the index of the longest axis of the bounding box.
+    pub fn longest_axis(&self) -> usize {
+        if self.x.size() > self.y.size() {
+            if self.x.size() > self.z.size() { 0 } else { 2 }
+        } else if self.y.size() > self.z.size() {
+            1
+        } else {
+            2
+        }
+    }
+
     fn axis_interval(&self, n: usize, r: &Ray) -> (&Interval, f64, f64) {
         if n == 1 {
             (&self.y, r.origin.y, r.dir.y)
@@ -65,4 +125,74 @@ impl AABB {
         }
         true
     }
+
+    /// `hit` for every ray of `packet`, each within its own interval.
+    pub fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_t: &[Interval; PACKET_WIDTH],
+    ) -> [bool; PACKET_WIDTH] {
+        #[cfg(target_arch = "x86_64")]
+        if packet::has_avx() {
+            // SAFETY: the CPU was just found to support AVX.
+            return unsafe { self.hit_packet_avx(packet, ray_t) };
+        }
+        std::array::from_fn(|lane| self.hit(&packet.rays[lane], ray_t[lane]))
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    #[target_feature(enable = "avx")]
+    fn hit_packet_avx(
+        &self,
+        packet: &RayPacket,
+        ray_t: &[Interval; PACKET_WIDTH],
+    ) -> [bool; PACKET_WIDTH] {
+        let side =
+            |side: fn(&Interval) -> f64| [0, 1, 2].map(|i| _mm256_set1_pd(side(self.axis(i))));
+        hit_lanes(
+            (side(|axis| axis.min), side(|axis| axis.max)),
+            (
+                packet.origin.each_ref().map(|lanes| packet::load(lanes)),
+                packet.inv_dir.each_ref().map(|lanes| packet::load(lanes)),
+            ),
+            (
+                packet::load(&ray_t.map(|interval| interval.min)),
+                packet::load(&ray_t.map(|interval| interval.max)),
+            ),
+        )
+    }
+}
+
+/// Slab test of four boxes against four rays, lane `i` testing box `i` with
+/// ray `i` within its interval, giving the same results as `AABB::hit`. The
+/// boxes are given by the lanes of their sides and the rays by those of their
+/// origin and inverse direction, per axis.
+///
+/// # Safety
+///
+/// The CPU must support AVX, as `packet::has_avx` checks.
+#[cfg(target_arch = "x86_64")]
+#[inline]
+#[target_feature(enable = "avx")]
+pub fn hit_lanes(
+    (box_min, box_max): ([__m256d; 3], [__m256d; 3]),
+    (origin, inv_dir): ([__m256d; 3], [__m256d; 3]),
+    (mut t_min, mut t_max): (__m256d, __m256d),
+) -> [bool; PACKET_WIDTH] {
+    for i in 0..3 {
+        let t0 = _mm256_mul_pd(_mm256_sub_pd(box_min[i], origin[i]), inv_dir[i]);
+        let t1 = _mm256_mul_pd(_mm256_sub_pd(box_max[i], origin[i]), inv_dir[i]);
+
+        // The min and max instructions pick their second operand unless the
+        // first compares less or greater, which makes the same choices as
+        // `hit` does, NaNs included.
+        t_min = _mm256_max_pd(_mm256_min_pd(t0, t1), t_min);
+        t_max = _mm256_min_pd(_mm256_max_pd(t1, t0), t_max);
+    }
+
+    // The interval only shrinks, so checking it once at the end rejects the
+    // same rays as checking it after every axis.
+    let hit = _mm256_movemask_pd(_mm256_cmp_pd::<_CMP_NLE_UQ>(t_max, t_min));
+    std::array::from_fn(|lane| hit & (1 << lane) != 0)
 }
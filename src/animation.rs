@@ -0,0 +1,141 @@
+use crate::{color::Color, vec3::Vec3};
+
+pub trait Lerp: Copy {
+    fn lerp(a: Self, b: Self, t: f64) -> Self;
+}
+
+impl Lerp for f64 {
+    fn lerp(a: Self, b: Self, t: f64) -> Self {
+        a + (b - a) * t
+    }
+}
+
+impl Lerp for Vec3 {
+    fn lerp(a: Self, b: Self, t: f64) -> Self {
+        a + (b - a) * t
+    }
+}
+
+impl Lerp for Color {
+    fn lerp(a: Self, b: Self, t: f64) -> Self {
+        Vec3::lerp(*a, *b, t).into()
+    }
+}
+
+/// How a track moves from a keyframe to the next one.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Interpolation {
+    /// Holds the value until the next keyframe.
+    Step,
+    #[default]
+    Linear,
+    /// Eases in and out of each keyframe.
+    Smooth,
+    /// Catmull-Rom spline through the neighbouring keyframes.
+    CatmullRom,
+}
+
+#[derive(Debug, Clone)]
+struct Keyframe<T> {
+    time: f64,
+    value: T,
+    interpolation: Interpolation,
+}
+
+/// Value changing over time, interpolated between keyframes and held
+/// constant before the first and after the last one.
+#[derive(Debug, Clone, Default)]
+pub struct Track<T> {
+    // Sorted by time
+    keys: Vec<Keyframe<T>>,
+}
+
+impl<T: Lerp> Track<T> {
+    pub fn new() -> Self {
+        Self { keys: Vec::new() }
+    }
+
+    pub fn constant(value: T) -> Self {
+        let mut track = Self::new();
+        track.insert(0.0, value);
+        track
+    }
+
+    /// Adds a linearly interpolated keyframe, replacing any other at the same
+    /// time.
+    pub fn insert(&mut self, time: f64, value: T) {
+        self.insert_with(time, value, Interpolation::Linear);
+    }
+
+    /// Adds a keyframe, `interpolation` applying up to the next keyframe.
+    pub fn insert_with(&mut self, time: f64, value: T, interpolation: Interpolation) {
+        let key = Keyframe {
+            time,
+            value,
+            interpolation,
+        };
+        match self.keys.binary_search_by(|k| k.time.total_cmp(&time)) {
+            Ok(idx) => self.keys[idx] = key,
+            Err(idx) => self.keys.insert(idx, key),
+        }
+    }
+
+    /// Builder style `insert_with`.
+    pub fn key(mut self, time: f64, value: T, interpolation: Interpolation) -> Self {
+        self.insert_with(time, value, interpolation);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    pub fn is_animated(&self) -> bool {
+        self.keys.len() > 1
+    }
+
+    /// Times of all the keyframes, in order.
+    pub fn times(&self) -> impl Iterator<Item = f64> + '_ {
+        self.keys.iter().map(|k| k.time)
+    }
+
+    pub fn value_at(&self, time: f64) -> T {
+        let idx = self.keys.partition_point(|k| k.time <= time);
+        if idx == 0 {
+            return self.keys.first().expect("track has no keyframes").value;
+        }
+        if idx == self.keys.len() {
+            return self.keys[idx - 1].value;
+        }
+
+        let k0 = &self.keys[idx - 1];
+        let k1 = &self.keys[idx];
+        let t = (time - k0.time) / (k1.time - k0.time);
+        match k0.interpolation {
+            Interpolation::Step => k0.value,
+            Interpolation::Linear => T::lerp(k0.value, k1.value, t),
+            Interpolation::Smooth => T::lerp(k0.value, k1.value, t * t * (3.0 - 2.0 * t)),
+            Interpolation::CatmullRom => {
+                // Missing neighbours at the ends are mirrored, Barry-Goldman
+                // evaluation only needs (extrapolated) lerps.
+                let (t0, p0) = match idx.checked_sub(2).map(|i| &self.keys[i]) {
+                    Some(k) => (k.time, k.value),
+                    None => (2.0 * k0.time - k1.time, T::lerp(k0.value, k1.value, -1.0)),
+                };
+                let (t3, p3) = match self.keys.get(idx + 1) {
+                    Some(k) => (k.time, k.value),
+                    None => (2.0 * k1.time - k0.time, T::lerp(k0.value, k1.value, 2.0)),
+                };
+                let (t1, p1, t2, p2) = (k0.time, k0.value, k1.time, k1.value);
+                let f = |a: T, ta: f64, b: T, tb: f64| T::lerp(a, b, (time - ta) / (tb - ta));
+
+                let a1 = f(p0, t0, p1, t1);
+                let a2 = f(p1, t1, p2, t2);
+                let a3 = f(p2, t2, p3, t3);
+                let b1 = f(a1, t0, a2, t2);
+                let b2 = f(a2, t1, a3, t3);
+                f(b1, t1, b2, t2)
+            }
+        }
+    }
+}
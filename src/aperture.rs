@@ -0,0 +1,108 @@
+use std::f64::consts::PI;
+
+use crate::{microfacet::luminance, texture::ImageTexture, vec3::Vec3};
+
+/// Shape of the lens opening, which gives out of focus highlights their shape.
+#[derive(Debug, Clone)]
+pub enum Aperture {
+    Circle,
+    /// Regular polygon formed by the diaphragm blades, `rotation` in degrees.
+    Polygon {
+        blades: u32,
+        rotation: f64,
+    },
+    /// Arbitrary shape, sampled proportionally to the image luminance.
+    Image(BokehImage),
+}
+
+impl Aperture {
+    /// Maps a uniform point of [0,1)^2 to a point of the aperture, scaled to
+    /// fit the unit disk.
+    pub fn sample(&self, (u1, u2): (f64, f64)) -> Vec3 {
+        match self {
+            Aperture::Circle => Vec3::sample_unit_disk((u1, u2)),
+            Aperture::Polygon { blades, rotation } => {
+                let blades = (*blades).max(3);
+                // All triangles fanning from the center have the same area,
+                // u1 picks one and what is left of it is reused within it.
+                let k = (u1 * blades as f64).floor().min((blades - 1) as f64);
+                let mut s = u1 * blades as f64 - k;
+                let mut t = u2;
+                let step = 2.0 * PI / blades as f64;
+                let a0 = rotation.to_radians() + k * step;
+                let v0 = Vec3::new(a0.cos(), a0.sin(), 0.0);
+                let v1 = Vec3::new((a0 + step).cos(), (a0 + step).sin(), 0.0);
+
+                if s + t > 1.0 {
+                    s = 1.0 - s;
+                    t = 1.0 - t;
+                }
+                s * v0 + t * v1
+            }
+            Aperture::Image(image) => image.sample((u1, u2)),
+        }
+    }
+}
+
+/// Piecewise constant 2D distribution over the pixels of a bokeh image.
+#[derive(Debug, Clone)]
+pub struct BokehImage {
+    width: usize,
+    height: usize,
+    // Cumulative weight of each row, then of each pixel within its row
+    row_cdf: Vec<f64>,
+    pixel_cdf: Vec<f64>,
+}
+
+impl BokehImage {
+    pub fn new(image: &ImageTexture) -> Self {
+        let (width, height) = (image.width(), image.height());
+        let mut row_cdf = Vec::with_capacity(height);
+        let mut pixel_cdf = Vec::with_capacity(width * height);
+
+        let mut total = 0.0;
+        for j in 0..height {
+            let mut row_total = 0.0;
+            for i in 0..width {
+                row_total += luminance(&image.pixel(i, j)).max(0.0);
+                pixel_cdf.push(row_total);
+            }
+            total += row_total;
+            row_cdf.push(total);
+        }
+        assert!(total > 0.0, "bokeh image must not be black");
+
+        Self {
+            width,
+            height,
+            row_cdf,
+            pixel_cdf,
+        }
+    }
+
+    fn sample(&self, (u1, u2): (f64, f64)) -> Vec3 {
+        // Where u falls within the chosen bin is uniform again and is reused
+        // to jitter within the pixel.
+        let invert = |cdf: &[f64], u: f64| {
+            let target = u * cdf[cdf.len() - 1];
+            let bin = cdf.partition_point(|&c| c <= target).min(cdf.len() - 1);
+            let start = if bin == 0 { 0.0 } else { cdf[bin - 1] };
+            let width = cdf[bin] - start;
+            let offset = if width > 0.0 {
+                (target - start) / width
+            } else {
+                0.5
+            };
+            (bin, offset.clamp(0.0, 1.0))
+        };
+
+        let (j, dy) = invert(&self.row_cdf, u1);
+        let row = &self.pixel_cdf[j * self.width..(j + 1) * self.width];
+        let (i, dx) = invert(row, u2);
+
+        // Map the image onto [-1,1]^2 with +y up
+        let x = (i as f64 + dx) / self.width as f64;
+        let y = (j as f64 + dy) / self.height as f64;
+        Vec3::new(2.0 * x - 1.0, 1.0 - 2.0 * y, 0.0)
+    }
+}
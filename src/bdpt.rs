@@ -0,0 +1,444 @@
+use std::f64::consts::PI;
+
+use crate::{
+    film::FilmSample,
+    hittable::{HitRecord, Hittable},
+    integrator::{Integrator, background, surface_sample},
+    interval::Interval,
+    light::{Light, LightList},
+    material::Material,
+    onb::Onb,
+    ray::Ray,
+    sampler::Sampler,
+    vec3::{Point, Vec3},
+};
+
+/// Bidirectional path tracer, connecting every vertex of a path traced from
+/// the camera to every vertex of one traced from a light, each connection
+/// weighted by multiple importance sampling against the other ways the same
+/// path could have been built. Light reaching the camera through small
+/// openings or from behind glass is found by the light paths.
+///
+/// Only surfaces whose material can be evaluated are connected, mirrors and
+/// glass being gone through by both paths. Light paths are not connected to
+/// the camera itself, as those land on other pixels. Spectral cameras are
+/// rendered in RGB.
+#[derive(Debug, Clone)]
+pub struct Bidirectional {
+    max_depth: u32,
+    lights: LightList,
+}
+
+impl Default for Bidirectional {
+    fn default() -> Self {
+        Self {
+            max_depth: 10,
+            lights: LightList::default(),
+        }
+    }
+}
+
+impl Bidirectional {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Most bounces of a complete path, from the camera to a light.
+    pub fn max_depth(mut self, depth: u32) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Emitters light paths start from. Those left out, and the sky, are
+    /// only found by camera paths.
+    pub fn lights(mut self, lights: LightList) -> Self {
+        self.lights = lights;
+        self
+    }
+}
+
+enum VertexKind<'a, M> {
+    Camera,
+    Light(&'a Light),
+    Surface {
+        hit: HitRecord<'a, M>,
+        // Entry of the light list when the surface is one of them
+        light: Option<&'a Light>,
+    },
+}
+
+/// Vertex of a camera or light path.
+struct Vertex<'a, M> {
+    kind: VertexKind<'a, M>,
+    p: Point,
+    // Shading normal, zero at the camera
+    n: Vec3,
+    // Throughput of the path up to this vertex, over its density
+    beta: Vec3,
+    // Area densities of reaching this vertex from the previous one, and
+    // from the next one going the other way
+    pdf_fwd: f64,
+    pdf_rev: f64,
+    // Scattering can't be evaluated, so paths can't be connected here
+    delta: bool,
+}
+
+impl<'a, M: Material> Vertex<'a, M> {
+    fn is_on_surface(&self) -> bool {
+        !matches!(self.kind, VertexKind::Camera)
+    }
+
+    fn light(&self) -> Option<&'a Light> {
+        match self.kind {
+            VertexKind::Light(light) => Some(light),
+            VertexKind::Surface { light, .. } => light,
+            VertexKind::Camera => None,
+        }
+    }
+
+    /// Unit vector from this vertex towards `other`.
+    fn direction_to(&self, other: &Vertex<M>) -> Vec3 {
+        (other.p - self.p).unit_vector()
+    }
+
+    /// Converts the solid angle density of sampling `next` from this vertex
+    /// into an area density at `next`.
+    fn area_density(&self, pdf_dir: f64, next: &Vertex<M>) -> f64 {
+        let w = next.p - self.p;
+        let distance_squared = w.length_squared();
+        if distance_squared == 0.0 {
+            return 0.0;
+        }
+        let mut pdf = pdf_dir / distance_squared;
+        if next.is_on_surface() {
+            pdf *= next.n.dot(&w).abs() / distance_squared.sqrt();
+        }
+        pdf
+    }
+
+    /// BSDF for light going between `prev` and `next` through this surface,
+    /// one for lights as their throughput holds the emitted radiance.
+    fn f(&self, prev: &Vertex<M>, next: &Vertex<M>) -> Vec3 {
+        match &self.kind {
+            VertexKind::Surface { hit, .. } => hit
+                .material
+                .evaluate(hit, &self.direction_to(prev), &self.direction_to(next))
+                .map_or(Vec3::default(), |(f, _)| *f),
+            _ => Vec3::new(1.0, 1.0, 1.0),
+        }
+    }
+
+    /// Area density of sampling `next` from this vertex, reached from `prev`.
+    fn pdf(&self, prev: Option<&Vertex<M>>, next: &Vertex<M>) -> f64 {
+        let (VertexKind::Surface { hit, .. }, Some(prev)) = (&self.kind, prev) else {
+            return self.pdf_light(next);
+        };
+        let pdf_dir = hit
+            .material
+            .evaluate(hit, &self.direction_to(prev), &self.direction_to(next))
+            .map_or(0.0, |(_, pdf)| pdf);
+        self.area_density(pdf_dir, next)
+    }
+
+    /// Area density at `next` of light leaving this point of a light, which
+    /// emits cosine distributed on both sides.
+    fn pdf_light(&self, next: &Vertex<M>) -> f64 {
+        let cos_theta = self.n.dot(&self.direction_to(next)).abs();
+        self.area_density(cos_theta / (2.0 * PI), next)
+    }
+
+    /// Area density of a light path starting at this point.
+    fn pdf_light_origin(&self, lights: &LightList) -> f64 {
+        self.light()
+            .map_or(0.0, |light| lights.pick_pdf() / light.area())
+    }
+}
+
+impl Bidirectional {
+    /// Extends `path` along `ray`, carrying the throughput `beta` and
+    /// sampled with the solid angle density `pdf_dir`, until it leaves the
+    /// scene, is absorbed or has `max_vertices` vertices. Returns the ray
+    /// that left the scene with its throughput.
+    fn random_walk<'a, M: Material>(
+        &'a self,
+        sampler: &mut impl Sampler,
+        world: &'a (impl Hittable<M> + ?Sized),
+        (mut ray, hit): (Ray, Option<HitRecord<'a, M>>),
+        (mut beta, mut pdf_dir): (Vec3, f64),
+        max_vertices: usize,
+        path: &mut Vec<Vertex<'a, M>>,
+    ) -> Option<(Ray, Vec3)> {
+        let light_walk = matches!(path.first().map(|v| &v.kind), Some(VertexKind::Light(_)));
+        let mut first_hit = Some(hit);
+        while path.len() < max_vertices {
+            let hit = first_hit
+                .take()
+                .unwrap_or_else(|| world.hit(&ray, Interval::new(0.001, f64::INFINITY)));
+            let Some(hit) = hit else {
+                return Some((ray, beta));
+            };
+            let scatter = hit.material.scatter(sampler, &ray, &hit);
+            // Only light paths carry power rather than importance.
+            let crossing = match &scatter {
+                Some(scatter) if light_walk => hit
+                    .material
+                    .transmitted_power_scale(&hit, &scatter.scattered.dir),
+                _ => 1.0,
+            };
+            // Densities of scattering forwards and back, none off surfaces
+            // that can't be evaluated
+            let densities = scatter.as_ref().map(|scatter| {
+                let wo = -ray.dir.unit_vector();
+                let wi = scatter.scattered.dir.unit_vector();
+                let (_, forward) = hit.material.evaluate(&hit, &wo, &wi)?;
+                let reverse = hit.material.evaluate(&hit, &wi, &wo);
+                Some((forward, reverse.map_or(0.0, |(_, pdf)| pdf)))
+            });
+            let mut vertex = Vertex {
+                p: hit.p,
+                n: hit.normal,
+                beta,
+                pdf_fwd: 0.0,
+                pdf_rev: 0.0,
+                delta: matches!(densities, Some(None)),
+                kind: VertexKind::Surface {
+                    light: self.lights.by_object(hit.object_id),
+                    hit,
+                },
+            };
+            let prev = path.last_mut().expect("walks start from an endpoint");
+            vertex.pdf_fwd = prev.area_density(pdf_dir, &vertex);
+
+            let Some(scatter) = scatter else {
+                path.push(vertex);
+                break;
+            };
+            let (forward, reverse) = densities.flatten().unwrap_or((0.0, 0.0));
+            prev.pdf_rev = vertex.area_density(reverse, prev);
+            pdf_dir = forward;
+            beta *= *scatter.attenuation * crossing;
+            ray = scatter.scattered;
+            path.push(vertex);
+        }
+        None
+    }
+
+    /// Path from a point of a light picked at random, with the light itself
+    /// as the first vertex.
+    fn light_path<'a, M: Material>(
+        &'a self,
+        sampler: &mut impl Sampler,
+        world: &'a (impl Hittable<M> + ?Sized),
+        time: f64,
+    ) -> Vec<Vertex<'a, M>> {
+        let mut path = Vec::new();
+        let Some(light) = self.lights.pick(sampler.get_1d()) else {
+            return path;
+        };
+        let (p, n) = light.sample_point(sampler.get_2d());
+        let pdf_pos = self.lights.pick_pdf() / light.area();
+        let side = if sampler.get_1d() < 0.5 { n } else { -n };
+        let local = Vec3::sample_cosine_direction(sampler.get_2d());
+        let pdf_dir = local.z / (2.0 * PI);
+        let vertex = Vertex {
+            kind: VertexKind::Light(light),
+            p,
+            n,
+            beta: *light.radiance() / pdf_pos,
+            pdf_fwd: pdf_pos,
+            pdf_rev: 0.0,
+            delta: false,
+        };
+        let beta = vertex.beta * (local.z / pdf_dir);
+        path.push(vertex);
+        if pdf_dir > 0.0 {
+            let ray = Ray::at_time(p, Onb::new(&side).transform(&local), time);
+            let hit = world.hit(&ray, Interval::new(0.001, f64::INFINITY));
+            let max_vertices = self.max_depth as usize;
+            self.random_walk(
+                sampler,
+                world,
+                (ray, hit),
+                (beta, pdf_dir),
+                max_vertices,
+                &mut path,
+            );
+        }
+        path
+    }
+
+    /// Light of the path made of the first `s` light and `t` camera
+    /// vertices, before weighting.
+    fn connect<M: Material>(
+        &self,
+        world: &(impl Hittable<M> + ?Sized),
+        light_path: &[Vertex<M>],
+        camera_path: &[Vertex<M>],
+        (s, t): (usize, usize),
+        time: f64,
+    ) -> Vec3 {
+        let pt = &camera_path[t - 1];
+        if s == 0 {
+            return match &pt.kind {
+                VertexKind::Surface { hit, .. } => pt.beta * *hit.material.emitted(hit),
+                _ => Vec3::default(),
+            };
+        }
+
+        let qs = &light_path[s - 1];
+        if qs.delta || pt.delta {
+            return Vec3::default();
+        }
+        let f_light = match s {
+            1 => qs.f(qs, pt),
+            _ => qs.f(&light_path[s - 2], pt),
+        };
+        let contribution = qs.beta * f_light * pt.f(&camera_path[t - 2], qs) * pt.beta;
+        if contribution.near_zero() {
+            return Vec3::default();
+        }
+
+        let w = pt.p - qs.p;
+        let distance = w.length();
+        let direction = w / distance;
+        let shadow = Ray::at_time(qs.p, direction, time);
+        if world.occluded(&shadow, Interval::new(0.001, distance - 0.001)) {
+            return Vec3::default();
+        }
+        let g = qs.n.dot(&direction).abs() * pt.n.dot(&direction).abs() / (distance * distance);
+        contribution * g
+    }
+
+    /// Balance heuristic weight of the connection of `s` light and `t`
+    /// camera vertices, against every other strategy that could have built
+    /// the same path.
+    fn mis_weight<M: Material>(
+        &self,
+        light_path: &[Vertex<M>],
+        camera_path: &[Vertex<M>],
+        (s, t): (usize, usize),
+    ) -> f64 {
+        let pt = &camera_path[t - 1];
+        let pt_minus = &camera_path[t - 2];
+        // Emitters outside the light list and the sky can only be hit.
+        if s == 0 && pt.light().is_none() {
+            return 1.0;
+        }
+
+        // Densities in both directions once the paths are joined.
+        let mut camera: Vec<(f64, f64, bool)> = camera_path[..t]
+            .iter()
+            .map(|v| (v.pdf_fwd, v.pdf_rev, v.delta))
+            .collect();
+        let mut light: Vec<(f64, f64, bool)> = light_path[..s]
+            .iter()
+            .map(|v| (v.pdf_fwd, v.pdf_rev, v.delta))
+            .collect();
+        // The joined vertices were connected, whatever their material, such
+        // as an emitter hit by the camera path.
+        camera[t - 1].2 = false;
+        if s == 0 {
+            camera[t - 1].1 = pt.pdf_light_origin(&self.lights);
+            camera[t - 2].1 = pt.pdf_light(pt_minus);
+        } else {
+            let qs = &light_path[s - 1];
+            let qs_minus = s.checked_sub(2).map(|i| &light_path[i]);
+            camera[t - 1].1 = qs.pdf(qs_minus, pt);
+            camera[t - 2].1 = pt.pdf(Some(qs), pt_minus);
+            light[s - 1].1 = pt.pdf(Some(pt_minus), qs);
+            light[s - 1].2 = false;
+            if let Some(qs_minus) = qs_minus {
+                light[s - 2].1 = qs.pdf(Some(pt), qs_minus);
+            }
+        }
+
+        let remap = |pdf: f64| if pdf == 0.0 { 1.0 } else { pdf };
+        let mut sum = 0.0;
+        // Strategies with fewer camera vertices, down to two as camera
+        // connections are not made.
+        let mut ratio = 1.0;
+        for i in (2..t).rev() {
+            ratio *= remap(camera[i].1) / remap(camera[i].0);
+            if !camera[i].2 && !camera[i - 1].2 {
+                sum += ratio;
+            }
+        }
+        // Strategies with fewer light vertices, down to hitting the light.
+        let mut ratio = 1.0;
+        for i in (0..s).rev() {
+            ratio *= remap(light[i].1) / remap(light[i].0);
+            let delta_before = i > 0 && light[i - 1].2;
+            if !light[i].2 && !delta_before {
+                sum += ratio;
+            }
+        }
+        1.0 / (1.0 + sum)
+    }
+}
+
+impl Integrator for Bidirectional {
+    fn sample_hit<'w, M: Material>(
+        &self,
+        sampler: &mut impl Sampler,
+        ray: &Ray,
+        hit: Option<HitRecord<'w, M>>,
+        world: &'w (impl Hittable<M> + ?Sized),
+    ) -> FilmSample {
+        let ray = Ray {
+            lambda: None,
+            ..ray.clone()
+        };
+        let mut result = FilmSample::default();
+        let mut add = |light: Vec3, bounce: usize| {
+            if bounce <= 1 {
+                result.direct += light;
+            } else {
+                result.indirect += light;
+            }
+        };
+
+        let mut camera_path = vec![Vertex {
+            kind: VertexKind::Camera,
+            p: ray.origin,
+            n: Vec3::default(),
+            beta: Vec3::new(1.0, 1.0, 1.0),
+            pdf_fwd: 0.0,
+            pdf_rev: 0.0,
+            delta: false,
+        }];
+        let escaped = self.random_walk(
+            sampler,
+            world,
+            (ray.clone(), hit),
+            (Vec3::new(1.0, 1.0, 1.0), 0.0),
+            self.max_depth as usize + 1,
+            &mut camera_path,
+        );
+        if let Some((escaped, beta)) = escaped {
+            add(beta * background(&escaped), camera_path.len() - 1);
+        }
+        let light_path = self.light_path(sampler, world, ray.time);
+
+        for t in 2..=camera_path.len() {
+            for s in 0..=light_path.len() {
+                let bounce = s + t - 2;
+                if bounce >= self.max_depth as usize {
+                    break;
+                }
+                let light = self.connect(world, &light_path, &camera_path, (s, t), ray.time);
+                if light.near_zero() {
+                    continue;
+                }
+                add(
+                    light * self.mis_weight(&light_path, &camera_path, (s, t)),
+                    bounce,
+                );
+            }
+        }
+
+        if let Some(VertexKind::Surface { hit, .. }) = camera_path.get(1).map(|v| &v.kind) {
+            result.surface = Some(surface_sample(hit, &ray));
+        }
+        result
+    }
+}
@@ -2,35 +2,180 @@ use crate::{
     aabb::AABB,
     hittable::{HitRecord, Hittable},
     interval::Interval,
+    packet::{PACKET_WIDTH, RayPacket},
     ray::Ray,
 };
 
-pub struct BVHNode<T> {
-    left: Box<T>,
-    right: Box<T>,
-    bbox: AABB,
+pub enum BVHNode<T> {
+    /// Object along with its index in the list the tree was built from, which
+    /// is reported as the object ID of its hits.
+    Leaf(u32, T),
+    Branch {
+        left: Box<BVHNode<T>>,
+        right: Box<BVHNode<T>>,
+        bbox: AABB,
+    },
 }
 
 impl<T> BVHNode<T> {
-    pub fn new_from_hittable_list() -> Self {
-        todo!()
+    pub fn new_from_hittable_list<M>(objects: Vec<T>) -> Self
+    where
+        T: Hittable<M>,
+    {
+        assert!(!objects.is_empty(), "cannot build a BVH without objects");
+        Self::build(
+            objects
+                .into_iter()
+                .zip(0..)
+                .map(|(obj, id)| (id, obj))
+                .collect(),
+        )
+    }
+
+    fn build<M>(mut objects: Vec<(u32, T)>) -> Self
+    where
+        T: Hittable<M>,
+    {
+        if objects.len() == 1 {
+            let (id, object) = objects.pop().unwrap();
+            return BVHNode::Leaf(id, object);
+        }
+
+        // Split along the longest axis of the box around all objects.
+        let bbox = objects.iter().fold(AABB::EMPTY, |acc, (_, elem)| {
+            AABB::new_from_boxes(&acc, &elem.bounding_box())
+        });
+        let axis = bbox.longest_axis();
+        objects.sort_by(|(_, a), (_, b)| {
+            let a_min = a.bounding_box().axis(axis).min;
+            let b_min = b.bounding_box().axis(axis).min;
+            a_min.total_cmp(&b_min)
+        });
+
+        let right_objects = objects.split_off(objects.len() / 2);
+        let left = Self::build(objects);
+        let right = Self::build(right_objects);
+
+        BVHNode::Branch {
+            left: Box::new(left),
+            right: Box::new(right),
+            bbox,
+        }
+    }
+
+    /// Closest hits of the rays of `packet` within `ray_t`, narrowing it
+    /// down to every hit found and storing them in `hits`. Searching the
+    /// right side up to the hits on the left side gives the same hits as
+    /// `hit` does for each ray.
+    fn hit_packet_into<'a, M>(
+        &'a self,
+        packet: &RayPacket,
+        ray_t: &mut [Interval; PACKET_WIDTH],
+        hits: &mut [Option<HitRecord<'a, M>>; PACKET_WIDTH],
+    ) where
+        T: Hittable<M>,
+    {
+        let (left, right, bbox) = match self {
+            BVHNode::Leaf(id, object) => {
+                let found = object.hit_packet(packet, ray_t);
+                for (lane, hit) in found.into_iter().enumerate() {
+                    if let Some(mut rec) = hit {
+                        rec.object_id = *id;
+                        ray_t[lane].max = rec.t;
+                        hits[lane] = Some(rec);
+                    }
+                }
+                return;
+            }
+            BVHNode::Branch { left, right, bbox } => (left, right, bbox),
+        };
+
+        // The whole packet goes down while any of its rays hits the box, the
+        // others being given empty intervals until it comes back up.
+        let inside = bbox.hit_packet(packet, ray_t);
+        if !inside.contains(&true) {
+            return;
+        }
+        let outer = *ray_t;
+        for (interval, inside) in ray_t.iter_mut().zip(inside) {
+            if !inside {
+                *interval = Interval::EMPTY;
+            }
+        }
+
+        left.hit_packet_into(packet, ray_t, hits);
+        right.hit_packet_into(packet, ray_t, hits);
+
+        for (lane, inside) in inside.into_iter().enumerate() {
+            if !inside {
+                ray_t[lane] = outer[lane];
+            }
+        }
     }
 }
 
 impl<M, T: Hittable<M>> Hittable<M> for BVHNode<T> {
-    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<M>> {
-        if !self.bbox.hit(ray, ray_t) {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_, M>> {
+        let (left, right, bbox) = match self {
+            BVHNode::Leaf(id, object) => {
+                return object.hit(ray, ray_t).map(|mut rec| {
+                    rec.object_id = *id;
+                    rec
+                });
+            }
+            BVHNode::Branch { left, right, bbox } => (left, right, bbox),
+        };
+
+        if !bbox.hit(ray, ray_t) {
             return None;
         }
 
-        let hit_left = self.left.hit(ray, ray_t);
+        let hit_left = left.hit(ray, ray_t);
         let t_max = hit_left.as_ref().map_or(ray_t.max, |rec| rec.t);
-        let hit_right = self.right.hit(ray, Interval::new(ray_t.min, t_max));
+        let hit_right = right.hit(ray, Interval::new(ray_t.min, t_max));
 
         hit_right.or(hit_left)
     }
 
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_t: &[Interval; PACKET_WIDTH],
+    ) -> [Option<HitRecord<'_, M>>; PACKET_WIDTH] {
+        let mut hits = Default::default();
+        let mut ray_t = *ray_t;
+        self.hit_packet_into(packet, &mut ray_t, &mut hits);
+        hits
+    }
+
+    fn occluded(&self, ray: &Ray, ray_t: Interval) -> bool {
+        match self {
+            BVHNode::Leaf(_, object) => object.occluded(ray, ray_t),
+            BVHNode::Branch { left, right, bbox } => {
+                bbox.hit(ray, ray_t) && (left.occluded(ray, ray_t) || right.occluded(ray, ray_t))
+            }
+        }
+    }
+
     fn bounding_box(&self) -> AABB {
-        self.bbox.clone()
+        match self {
+            BVHNode::Leaf(_, object) => object.bounding_box(),
+            BVHNode::Branch { bbox, .. } => bbox.clone(),
+        }
+    }
+
+    fn traversal_steps(&self, ray: &Ray, ray_t: Interval) -> u32 {
+        match self {
+            BVHNode::Leaf(_, object) => object.traversal_steps(ray, ray_t),
+            BVHNode::Branch { left, right, bbox } => {
+                if !bbox.hit(ray, ray_t) {
+                    return 1;
+                }
+                // The right side is searched up to the left hit, as in `hit`.
+                let t_max = left.hit(ray, ray_t).map_or(ray_t.max, |rec| rec.t);
+                1 + left.traversal_steps(ray, ray_t)
+                    + right.traversal_steps(ray, Interval::new(ray_t.min, t_max))
+            }
+        }
     }
 }
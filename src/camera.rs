@@ -1,28 +1,87 @@
-use rand::Rng;
+use std::{
+    borrow::Cow,
+    f64::consts::PI,
+    ops::Range,
+    time::{Duration, Instant},
+};
+
+use rayon::prelude::*;
 
 use crate::{
-    color::Color,
+    animation::Track,
+    aperture::Aperture,
+    film::{Film, FilmSample, FilmTile},
+    filter::Filter,
     hittable::Hittable,
+    integrator::{Integrator, IntegratorKind},
     interval::Interval,
     material::Material,
+    packet::{PACKET_WIDTH, RayPacket},
+    progressive::{PixelEstimate, Progress},
     ray::Ray,
+    sampler::{SamplePattern, Sampler, SamplerKind},
+    shutter::ShutterCurve,
+    spectrum::SampledWavelengths,
     vec3::{Point, Vec3},
 };
 
+/// How directions around the camera map onto the image.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Projection {
+    /// Pinhole or thin lens, framed by `vfov` or the physical lens.
+    Perspective,
+    /// Parallel rays along the view direction, `height` being the extent of
+    /// the image in scene units.
+    Orthographic { height: f64 },
+    /// Equidistant fisheye, `fov` in degrees spanning the image height.
+    Fisheye { fov: f64 },
+    /// Full sphere in latitude / longitude, best with a 2:1 image.
+    Equirectangular,
+    /// Six cube faces laid out 3x2 as +X -X +Y / -Y +Z -Z in the camera
+    /// frame, best with a 3:2 image.
+    Cubemap,
+}
+
 #[derive(Debug, Clone)]
 pub struct CameraBuilder {
     image_width: u32,
     aspect_ratio: f64,
     samples_per_pixel: u32,
-    max_depth: u32,
+    samples_per_pass: u32,
+    time_budget: Option<Duration>,
+    variance_threshold: Option<f64>,
+    adaptive_error: Option<f64>,
+    min_samples_per_pixel: u32,
+    max_samples_per_pixel: Option<u32>,
+    sampler: SamplePattern,
+    filter: Filter,
+    integrator: IntegratorKind,
 
     vfov: f64,
     lookfrom: Point,
     lookat: Point,
     vup: Vec3,
+    lookfrom_keys: Track<Point>,
+    lookat_keys: Track<Point>,
+    vfov_keys: Track<f64>,
+    projection: Projection,
+
+    shutter_open: f64,
+    shutter_close: f64,
+    shutter_curve: ShutterCurve,
 
     defocus_angle: f64,
     focus_dist: f64,
+    defocus_angle_keys: Track<f64>,
+    focus_dist_keys: Track<f64>,
+    aperture: Aperture,
+
+    // Physical lens, overriding vfov and defocus_angle when set
+    sensor_size: (f64, f64),
+    focal_length: Option<f64>,
+    f_stop: Option<f64>,
+
+    spectral: bool,
 }
 
 impl Default for CameraBuilder {
@@ -31,15 +90,41 @@ impl Default for CameraBuilder {
             image_width: 100,
             aspect_ratio: 1.0,
             samples_per_pixel: 10,
-            max_depth: 10,
+            samples_per_pass: 4,
+            time_budget: None,
+            variance_threshold: None,
+            adaptive_error: None,
+            min_samples_per_pixel: 16,
+            max_samples_per_pixel: None,
+            sampler: SamplePattern::Independent,
+            filter: Filter::default(),
+            integrator: IntegratorKind::default(),
 
             vfov: 90.0,
             lookfrom: Point::new(0.0, 0.0, 0.0),
             lookat: Point::new(0.0, 0.0, -1.0),
             vup: Vec3::new(0.0, 1.0, 0.0),
+            lookfrom_keys: Track::new(),
+            lookat_keys: Track::new(),
+            vfov_keys: Track::new(),
+            projection: Projection::Perspective,
+
+            shutter_open: 0.0,
+            shutter_close: 1.0,
+            shutter_curve: ShutterCurve::Box,
 
             defocus_angle: 0.0,
             focus_dist: 10.0,
+            defocus_angle_keys: Track::new(),
+            focus_dist_keys: Track::new(),
+            aperture: Aperture::Circle,
+
+            // Full frame 35mm
+            sensor_size: (36.0, 24.0),
+            focal_length: None,
+            f_stop: None,
+
+            spectral: false,
         }
     }
 }
@@ -64,8 +149,63 @@ impl CameraBuilder {
         self
     }
 
-    pub fn max_depth(mut self, depth: u32) -> Self {
-        self.max_depth = depth;
+    /// Samples added to every pixel by each progressive pass.
+    pub fn samples_per_pass(mut self, spp: u32) -> Self {
+        self.samples_per_pass = spp.max(1);
+        self
+    }
+
+    /// Stops rendering after the pass that exceeds `budget`.
+    pub fn time_budget(mut self, budget: Duration) -> Self {
+        self.time_budget = Some(budget);
+        self
+    }
+
+    /// Stops rendering once the estimated variance of every pixel's mean
+    /// luminance is below `threshold`.
+    pub fn variance_threshold(mut self, threshold: f64) -> Self {
+        self.variance_threshold = Some(threshold);
+        self
+    }
+
+    /// Stops sampling each pixel on its own once the standard error of its
+    /// luminance is below `max_error` relative to the luminance.
+    pub fn adaptive_error(mut self, max_error: f64) -> Self {
+        self.adaptive_error = Some(max_error);
+        self
+    }
+
+    /// Samples every pixel takes before adaptive sampling may stop it.
+    pub fn min_samples_per_pixel(mut self, spp: u32) -> Self {
+        self.min_samples_per_pixel = spp;
+        self
+    }
+
+    /// Most samples a pixel takes with adaptive sampling, pixels still above
+    /// the error after `samples_per_pixel` carrying on up to it. Four times
+    /// `samples_per_pixel` by default.
+    pub fn max_samples_per_pixel(mut self, spp: u32) -> Self {
+        self.max_samples_per_pixel = Some(spp);
+        self
+    }
+
+    /// Distribution of the sample values, stratified and low discrepancy
+    /// patterns converge faster than independent random numbers.
+    pub fn sampler(mut self, pattern: SamplePattern) -> Self {
+        self.sampler = pattern;
+        self
+    }
+
+    /// Reconstruction filter spreading every sample over the pixels around
+    /// it, a box of radius 0.5 by default.
+    pub fn filter(mut self, filter: Filter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Estimates the light of every camera ray, a path tracer by default.
+    pub fn integrator(mut self, integrator: IntegratorKind) -> Self {
+        self.integrator = integrator;
         self
     }
 
@@ -89,6 +229,43 @@ impl CameraBuilder {
         self
     }
 
+    /// Animates the camera position, overriding `lookfrom`.
+    pub fn lookfrom_keyframe(mut self, time: f64, p: Point) -> Self {
+        self.lookfrom_keys.insert(time, p);
+        self
+    }
+
+    /// Animates the point looked at, overriding `lookat`.
+    pub fn lookat_keyframe(mut self, time: f64, p: Point) -> Self {
+        self.lookat_keys.insert(time, p);
+        self
+    }
+
+    /// Animates the field of view, overriding `vfov`.
+    pub fn vfov_keyframe(mut self, time: f64, angle: f64) -> Self {
+        self.vfov_keys.insert(time, angle);
+        self
+    }
+
+    /// Times the shutter opens and closes. Moving objects go from their start
+    /// to end position over [0,1], so (0.0, 0.5) matches a 180° shutter.
+    pub fn shutter(mut self, open: f64, close: f64) -> Self {
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self
+    }
+
+    pub fn shutter_curve(mut self, curve: ShutterCurve) -> Self {
+        self.shutter_curve = curve;
+        self
+    }
+
+    /// Only the perspective projection models depth of field.
+    pub fn projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self
+    }
+
     pub fn defocus_angle(mut self, angle: f64) -> Self {
         self.defocus_angle = angle;
         self
@@ -99,170 +276,654 @@ impl CameraBuilder {
         self
     }
 
-    pub fn build(self) -> Camera {
-        let image_height = (self.image_width as f64 / self.aspect_ratio).max(1.0) as u32;
+    /// Animates the defocus angle, overriding `defocus_angle`.
+    pub fn defocus_angle_keyframe(mut self, time: f64, angle: f64) -> Self {
+        self.defocus_angle_keys.insert(time, angle);
+        self
+    }
 
-        let center = self.lookfrom;
+    /// Animates the focus distance, overriding `focus_dist`.
+    pub fn focus_dist_keyframe(mut self, time: f64, dist: f64) -> Self {
+        self.focus_dist_keys.insert(time, dist);
+        self
+    }
 
-        // Viewport dimensions
-        let theta = self.vfov.to_radians();
-        let h = (theta / 2.0).tan();
-        let viewport_height = 2.0 * h * self.focus_dist;
-        let viewport_width = viewport_height * (self.image_width as f64 / image_height as f64);
+    pub fn aperture(mut self, aperture: Aperture) -> Self {
+        self.aperture = aperture;
+        self
+    }
 
-        // Calculate the u,v,w unit basis vectors for the camera coordinate frame.
-        let w = (self.lookfrom - self.lookat).unit_vector();
-        let u = self.vup.cross(&w).unit_vector();
-        let v = w.cross(&u);
+    /// Sensor width and height in millimetres.
+    pub fn sensor_size(mut self, width: f64, height: f64) -> Self {
+        self.sensor_size = (width, height);
+        self
+    }
 
-        // Calculate the vectors across the horizontal and down the vertical viewport edges.
-        let viewport_u = viewport_width * u;
-        let viewport_v = viewport_height * -v;
+    /// Lens focal length in millimetres, replaces `vfov`.
+    pub fn focal_length(mut self, focal_length: f64) -> Self {
+        self.focal_length = Some(focal_length);
+        self
+    }
 
-        // Calculate the horizontal and vertical delta vectors from pixel to pixel.
-        let pixel_delta_u = viewport_u / self.image_width as f64;
-        let pixel_delta_v = viewport_v / image_height as f64;
+    /// Lens f-number, replaces `defocus_angle` when a focal length is set.
+    /// Scene units are taken to be metres.
+    pub fn f_stop(mut self, f_stop: f64) -> Self {
+        self.f_stop = Some(f_stop);
+        self
+    }
 
-        // Calculate the location of the upper left pixel.
-        let viewport_upper_left =
-            center - (self.focus_dist * w) - viewport_u / 2.0 - viewport_v / 2.0;
+    /// Trace hero wavelength samples instead of RGB triples, needed for
+    /// dispersive materials.
+    pub fn spectral(mut self, spectral: bool) -> Self {
+        self.spectral = spectral;
+        self
+    }
 
-        let pixel00_loc = viewport_upper_left + (pixel_delta_u + pixel_delta_v) * 0.5;
+    pub fn build(self) -> Camera {
+        let image_height = (self.image_width as f64 / self.aspect_ratio).max(1.0) as u32;
 
-        // Calculate the camera defocus disk basis vectors.
-        let defocus_radius = self.focus_dist * (self.defocus_angle / 2.0).to_radians().tan();
-        let defocus_disk_u = u * defocus_radius;
-        let defocus_disk_v = v * defocus_radius;
+        let lens = Lens {
+            projection: self.projection,
+            sensor_size: self.sensor_size,
+            focal_length: self.focal_length,
+            f_stop: self.f_stop,
+            image_width: self.image_width,
+            image_height,
+        };
+        let keys_or = |keys: Track<f64>, value: f64| {
+            if keys.is_empty() {
+                Track::constant(value)
+            } else {
+                keys
+            }
+        };
+        let vfov = keys_or(self.vfov_keys, self.vfov);
+        let focus_dist = keys_or(self.focus_dist_keys, self.focus_dist);
+        let defocus_angle = keys_or(self.defocus_angle_keys, self.defocus_angle);
+        let viewport = lens.viewport(
+            vfov.value_at(self.shutter_open),
+            focus_dist.value_at(self.shutter_open),
+            defocus_angle.value_at(self.shutter_open),
+        );
 
-        let pixel_samples_scale = 1.0 / self.samples_per_pixel as f64;
+        let lookfrom = if self.lookfrom_keys.is_empty() {
+            Track::constant(self.lookfrom)
+        } else {
+            self.lookfrom_keys
+        };
+        let lookat = if self.lookat_keys.is_empty() {
+            Track::constant(self.lookat)
+        } else {
+            self.lookat_keys
+        };
+        let view = View::new(
+            lookfrom.value_at(self.shutter_open),
+            lookat.value_at(self.shutter_open),
+            self.vup,
+            &viewport,
+        );
 
         eprintln!("Image: {}x{}", self.image_width, image_height);
         eprintln!(
             "Camera: center: {}, focus_dist: {}, viewport_height: {}, viewport_width: {}",
-            center, self.focus_dist, viewport_height, viewport_width
+            view.center,
+            focus_dist.value_at(self.shutter_open),
+            viewport.height,
+            viewport.width
         );
         eprintln!(
-            "ViewPort: viewport_u: {}, view_port_v: {}, pixel_delta_u: {}, pixel_delta_v: {}",
-            viewport_u, viewport_v, pixel_delta_u, pixel_delta_v
+            "ViewPort: pixel_delta_u: {}, pixel_delta_v: {}",
+            view.pixel_delta_u, view.pixel_delta_v
         );
 
         Camera {
             image_width: self.image_width,
             image_height,
-            center,
-            pixel00_loc,
-            pixel_delta_u,
-            pixel_delta_v,
+            projection: self.projection,
             samples_per_pixel: self.samples_per_pixel,
-            pixel_samples_scale,
-            max_depth: self.max_depth,
-            defocus_angle: self.defocus_angle,
-            defocus_disk_u,
-            defocus_disk_v,
+            samples_per_pass: self.samples_per_pass,
+            time_budget: self.time_budget,
+            variance_threshold: self.variance_threshold,
+            adaptive_error: self.adaptive_error,
+            min_samples_per_pixel: self.min_samples_per_pixel,
+            max_samples_per_pixel: match self.adaptive_error {
+                Some(_) => self
+                    .max_samples_per_pixel
+                    .unwrap_or(4 * self.samples_per_pixel)
+                    .max(self.samples_per_pixel),
+                None => self.samples_per_pixel,
+            },
+            sampler: self.sampler,
+            filter: self.filter,
+            integrator: self.integrator,
+            lens,
+            viewport,
+            view,
+            lookfrom,
+            lookat,
+            vup: self.vup,
+            vfov,
+            focus_dist,
+            defocus_angle,
+            shutter_open: self.shutter_open,
+            shutter_close: self.shutter_close,
+            shutter_curve: self.shutter_curve,
+            aperture: self.aperture,
+            spectral: self.spectral,
         }
     }
 }
 
+/// Camera settings the viewport is derived from, besides the animated ones.
 #[derive(Debug, Clone)]
-pub struct Camera {
-    pub image_width: u32,
-    pub image_height: u32,
+struct Lens {
+    projection: Projection,
+    sensor_size: (f64, f64),
+    focal_length: Option<f64>,
+    f_stop: Option<f64>,
+    image_width: u32,
+    image_height: u32,
+}
+
+impl Lens {
+    fn viewport(&self, vfov: f64, focus_dist: f64, defocus_angle: f64) -> Viewport {
+        let image_aspect = self.image_width as f64 / self.image_height as f64;
+
+        let vfov = match self.focal_length {
+            Some(focal_length) => {
+                // Fit the sensor to the image along its constraining dimension.
+                let (sensor_width, sensor_height) = self.sensor_size;
+                let gate_height = if image_aspect >= sensor_width / sensor_height {
+                    sensor_width / image_aspect
+                } else {
+                    sensor_height
+                };
+                2.0 * (gate_height / (2.0 * focal_length)).atan().to_degrees()
+            }
+            None => vfov,
+        };
+
+        // Viewport dimensions
+        let theta = vfov.to_radians();
+        let h = (theta / 2.0).tan();
+        let viewport_height = match self.projection {
+            Projection::Orthographic { height } => height,
+            _ => 2.0 * h * focus_dist,
+        };
+        let viewport_width = viewport_height * image_aspect;
+
+        // The orthographic viewport sits on the camera itself.
+        let viewport_distance = match self.projection {
+            Projection::Orthographic { .. } => 0.0,
+            _ => focus_dist,
+        };
+
+        let defocus_radius = match (self.focal_length, self.f_stop) {
+            // Aperture diameter is the focal length over the f-number, in mm.
+            (Some(focal_length), Some(f_stop)) => focal_length / f_stop / 2.0 / 1000.0,
+            _ => focus_dist * (defocus_angle / 2.0).to_radians().tan(),
+        };
+
+        Viewport {
+            width: viewport_width,
+            height: viewport_height,
+            distance: viewport_distance,
+            defocus_radius,
+            image_width: self.image_width,
+            image_height: self.image_height,
+        }
+    }
+}
+
+/// Image plane and lens, independent of where the camera is placed.
+#[derive(Debug, Clone)]
+struct Viewport {
+    width: f64,
+    height: f64,
+    // Distance from the camera center to the viewport
+    distance: f64,
+    defocus_radius: f64,
+    image_width: u32,
+    image_height: u32,
+}
+
+/// Camera placement and the pixel grid derived from it.
+#[derive(Debug, Clone)]
+struct View {
     center: Point,
+    // Camera frame basis vectors
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
     pixel00_loc: Point,
     pixel_delta_u: Vec3,
     pixel_delta_v: Vec3,
-    pixel_samples_scale: f64,
-    // Count of random samples for each pixel
-    samples_per_pixel: u32,
-    // Maximum number of ray bounces into scene
-    max_depth: u32,
-
-    defocus_angle: f64,
+    defocus_radius: f64,
     defocus_disk_u: Vec3,
     defocus_disk_v: Vec3,
 }
 
-fn ray_color<M: Material, T: Hittable<M>>(
-    rng: &mut impl Rng,
-    ray: &Ray,
-    depth: u32,
-    world: &[T],
-) -> Vec3 {
-    // If we've exceeded the ray bounce limit, no more light is gathered.
-    if depth == 0 {
-        return Vec3::default();
-    }
-    if let Some(h) = world.hit(ray, Interval::new(0.001, f64::INFINITY)) {
-        if let Some(s) = h.material.scatter(rng, ray, &h) {
-            return *s.attenuation * ray_color(rng, &s.scattered, depth - 1, world);
+impl View {
+    fn new(lookfrom: Point, lookat: Point, vup: Vec3, viewport: &Viewport) -> Self {
+        let center = lookfrom;
+
+        // Calculate the u,v,w unit basis vectors for the camera coordinate frame.
+        let w = (lookfrom - lookat).unit_vector();
+        let u = vup.cross(&w).unit_vector();
+        let v = w.cross(&u);
+
+        // Calculate the vectors across the horizontal and down the vertical viewport edges.
+        let viewport_u = viewport.width * u;
+        let viewport_v = viewport.height * -v;
+
+        // Calculate the horizontal and vertical delta vectors from pixel to pixel.
+        let pixel_delta_u = viewport_u / viewport.image_width as f64;
+        let pixel_delta_v = viewport_v / viewport.image_height as f64;
+
+        // Calculate the location of the upper left pixel.
+        let viewport_upper_left =
+            center - (viewport.distance * w) - viewport_u / 2.0 - viewport_v / 2.0;
+
+        let pixel00_loc = viewport_upper_left + (pixel_delta_u + pixel_delta_v) * 0.5;
+
+        // Calculate the camera defocus disk basis vectors.
+        let defocus_disk_u = u * viewport.defocus_radius;
+        let defocus_disk_v = v * viewport.defocus_radius;
+
+        Self {
+            center,
+            u,
+            v,
+            w,
+            pixel00_loc,
+            pixel_delta_u,
+            pixel_delta_v,
+            defocus_radius: viewport.defocus_radius,
+            defocus_disk_u,
+            defocus_disk_v,
         }
-        return Vec3::new(0.0, 0.0, 0.0);
     }
+}
+
+#[derive(Debug, Clone)]
+pub struct Camera {
+    pub image_width: u32,
+    pub image_height: u32,
+    projection: Projection,
+    // Count of random samples for each pixel, the most a progressive render
+    // takes unless sampling is adaptive
+    samples_per_pixel: u32,
+    samples_per_pass: u32,
+    time_budget: Option<Duration>,
+    variance_threshold: Option<f64>,
+    adaptive_error: Option<f64>,
+    min_samples_per_pixel: u32,
+    // Most samples a pixel takes, past `samples_per_pixel` with adaptive
+    // sampling
+    max_samples_per_pixel: u32,
+    sampler: SamplePattern,
+    filter: Filter,
+    integrator: IntegratorKind,
+
+    lens: Lens,
+    // Viewport and view at shutter open, used as is unless the camera is
+    // animated
+    viewport: Viewport,
+    view: View,
+    lookfrom: Track<Point>,
+    lookat: Track<Point>,
+    vup: Vec3,
+    vfov: Track<f64>,
+    focus_dist: Track<f64>,
+    defocus_angle: Track<f64>,
 
-    let unit_direction = ray.dir.unit_vector();
-    let a = 0.5 * (unit_direction.y + 1.0);
+    shutter_open: f64,
+    shutter_close: f64,
+    shutter_curve: ShutterCurve,
 
-    let white = Vec3::new(1.0, 1.0, 1.0);
-    let blue = Vec3::new(0.5, 0.7, 1.0);
+    aperture: Aperture,
 
-    white * (1.0 - a) + blue * a
+    spectral: bool,
 }
 
-fn sample_square(rng: &mut impl Rng) -> Vec3 {
-    // Returns the vector to a random point in the [-.5,-.5]-[+.5,+.5] unit square.
+fn sample_square(sampler: &mut impl Sampler) -> Vec3 {
+    // Returns the vector to a sampled point in the [-.5,-.5]-[+.5,+.5] unit square.
+    let (x, y) = sampler.get_2d();
     Vec3 {
-        x: rng.random::<f64>() - 0.5,
-        y: rng.random::<f64>() - 0.5,
+        x: x - 0.5,
+        y: y - 0.5,
         z: 0.0,
     }
 }
 
 impl Camera {
-    fn defocus_disk_sample(&self, rng: &mut impl Rng) -> Vec3 {
-        // Returns a random point in the camera defocus disk.
-        let p = Vec3::random_in_unit_disk(rng);
-        self.center + (p.x * self.defocus_disk_u) + (p.y * self.defocus_disk_v)
+    fn view_at(&self, time: f64) -> Cow<'_, View> {
+        let lens_animated = self.vfov.is_animated()
+            || self.focus_dist.is_animated()
+            || self.defocus_angle.is_animated();
+        if !lens_animated && !self.lookfrom.is_animated() && !self.lookat.is_animated() {
+            return Cow::Borrowed(&self.view);
+        }
+
+        let viewport = if lens_animated {
+            Cow::Owned(self.lens.viewport(
+                self.vfov.value_at(time),
+                self.focus_dist.value_at(time),
+                self.defocus_angle.value_at(time),
+            ))
+        } else {
+            Cow::Borrowed(&self.viewport)
+        };
+        Cow::Owned(View::new(
+            self.lookfrom.value_at(time),
+            self.lookat.value_at(time),
+            self.vup,
+            &viewport,
+        ))
     }
 
-    fn get_ray(&self, rng: &mut impl Rng, i: u32, j: u32) -> Ray {
-        // Construct a camera ray originating from the origin and directed at randomly sampled
-        // point around the pixel location i, j.
+    fn sample_time(&self, sampler: &mut impl Sampler) -> f64 {
+        let fraction = self.shutter_curve.sample(sampler.get_1d());
+        self.shutter_open + (self.shutter_close - self.shutter_open) * fraction
+    }
 
-        let offset = sample_square(rng);
-        let pixel_sample = self.pixel00_loc
-            + ((i as f64 + offset.x) * self.pixel_delta_u)
-            + ((j as f64 + offset.y) * self.pixel_delta_v);
+    fn defocus_disk_sample(&self, sampler: &mut impl Sampler, view: &View) -> Vec3 {
+        // Returns a sampled point in the camera defocus disk, shaped by the aperture.
+        let p = self.aperture.sample(sampler.get_2d());
+        view.center + (p.x * view.defocus_disk_u) + (p.y * view.defocus_disk_v)
+    }
 
-        let ray_origin = if self.defocus_angle <= 0.0 {
-            self.center
-        } else {
-            self.defocus_disk_sample(rng)
+    fn panoramic_direction(&self, view: &View, x: f64, y: f64) -> Option<Vec3> {
+        // Direction through the normalized image position x, y in [0,1],
+        // expressed in the camera frame then transformed to world space.
+        let local = match self.projection {
+            Projection::Fisheye { fov } => {
+                let aspect = self.image_width as f64 / self.image_height as f64;
+                let px = (2.0 * x - 1.0) * aspect;
+                let py = 1.0 - 2.0 * y;
+                let r = (px * px + py * py).sqrt();
+                let theta = r * (fov / 2.0).to_radians();
+                if theta > PI {
+                    return None;
+                }
+                let phi = py.atan2(px);
+                Vec3::new(
+                    theta.sin() * phi.cos(),
+                    theta.sin() * phi.sin(),
+                    -theta.cos(),
+                )
+            }
+            Projection::Equirectangular => {
+                let phi = (x - 0.5) * 2.0 * PI;
+                let lat = (0.5 - y) * PI;
+                Vec3::new(lat.cos() * phi.sin(), lat.sin(), -lat.cos() * phi.cos())
+            }
+            Projection::Cubemap => {
+                let fx = (x * 3.0).min(2.999);
+                let fy = (y * 2.0).min(1.999);
+                let face = fy as usize * 3 + fx as usize;
+                let a = 2.0 * fx.fract() - 1.0;
+                let b = 2.0 * fy.fract() - 1.0;
+                match face {
+                    0 => Vec3::new(1.0, -b, -a),
+                    1 => Vec3::new(-1.0, -b, a),
+                    2 => Vec3::new(a, 1.0, b),
+                    3 => Vec3::new(a, -1.0, -b),
+                    4 => Vec3::new(a, -b, 1.0),
+                    _ => Vec3::new(-a, -b, -1.0),
+                }
+            }
+            Projection::Perspective | Projection::Orthographic { .. } => return None,
+        };
+
+        Some(local.x * view.u + local.y * view.v + local.z * view.w)
+    }
+
+    fn get_ray(&self, sampler: &mut impl Sampler, (x, y): (f64, f64)) -> Option<Ray> {
+        // Construct a camera ray originating from the origin and directed at
+        // the film position (x, y), in pixels from the top left corner of the
+        // image. Points outside of the image circle of a fisheye have no ray.
+
+        // The time comes before the lens as the camera itself may be moving.
+        let ray_time = self.sample_time(sampler);
+        let view = self.view_at(ray_time);
+
+        let pixel_sample =
+            view.pixel00_loc + ((x - 0.5) * view.pixel_delta_u) + ((y - 0.5) * view.pixel_delta_v);
+
+        let (ray_origin, ray_dir) = match self.projection {
+            Projection::Perspective => {
+                let ray_origin = if view.defocus_radius <= 0.0 {
+                    view.center
+                } else {
+                    self.defocus_disk_sample(sampler, &view)
+                };
+                (ray_origin, pixel_sample - ray_origin)
+            }
+            Projection::Orthographic { .. } => (pixel_sample, -view.w),
+            _ => {
+                let x = x / self.image_width as f64;
+                let y = y / self.image_height as f64;
+                (view.center, self.panoramic_direction(&view, x, y)?)
+            }
         };
-        let ray_time = rng.random();
 
-        Ray::at_time(ray_origin, pixel_sample - ray_origin, ray_time)
+        let mut ray = Ray::at_time(ray_origin, ray_dir, ray_time);
+        if self.spectral {
+            ray.lambda = Some(SampledWavelengths::sample_uniform(sampler.get_1d()));
+        }
+        Some(ray)
     }
 
-    pub fn render<M: Material, T: Hittable<M>>(
+    /// Light and film data of a camera sample through the continuous image
+    /// position `film`, in pixels.
+    pub fn sample_pixel<M: Material>(
         &self,
-        rng: &mut impl Rng,
-        world: &[T],
-    ) -> Vec<Color> {
-        let mut pixels = Vec::with_capacity((self.image_width * self.image_height) as usize);
-        for j in 0..self.image_height {
-            eprint!("\rScanlines remaining: {} ", self.image_height - j);
-
-            for i in 0..self.image_width {
-                let mut color = Vec3::default();
-                for _sample in 0..self.samples_per_pixel {
-                    let ray = self.get_ray(rng, i, j);
-                    color += ray_color(rng, &ray, self.max_depth, world);
+        sampler: &mut impl Sampler,
+        world: &(impl Hittable<M> + ?Sized),
+        film: (f64, f64),
+    ) -> FilmSample {
+        let Some(ray) = self.get_ray(sampler, film) else {
+            return FilmSample::default();
+        };
+        self.integrator.sample(sampler, &ray, world)
+    }
+
+    fn render_tile<M: Material>(
+        &self,
+        seed: u64,
+        world: &(impl Hittable<M> + ?Sized),
+        tile: &Tile,
+        samples: Range<u32>,
+        estimates: &mut [PixelEstimate],
+    ) -> FilmTile {
+        let mut film = FilmTile::new(self.filter, tile.x0, tile.y0, tile.x1, tile.y1);
+
+        // Samples only depend on the pixel and sample index, not on how the
+        // image is split or on what earlier samples consumed.
+        let mut samplers: [SamplerKind; PACKET_WIDTH] =
+            std::array::from_fn(|_| SamplerKind::new(self.sampler, seed, self.samples_per_pixel));
+        for ((i, j), estimate) in tile.pixels().zip(estimates) {
+            if !self.needs_samples(estimate) {
+                continue;
+            }
+            // The camera rays of a pixel start close together, so they are
+            // traced to their first hit as packets, every lane with its own
+            // sampler. The samples still reach the film in order.
+            for first in samples.clone().step_by(PACKET_WIDTH) {
+                let lanes = (first..samples.end).take(PACKET_WIDTH);
+                let mut positions = [(0.0, 0.0); PACKET_WIDTH];
+                let mut rays: [Option<Ray>; PACKET_WIDTH] = Default::default();
+                for (lane, sample) in lanes.clone().enumerate() {
+                    let sampler = &mut samplers[lane];
+                    sampler.start_pixel_sample((i, j), sample);
+                    // The position within the pixel takes the first, best
+                    // distributed, dimensions.
+                    let offset = sample_square(sampler);
+                    positions[lane] = (i as f64 + 0.5 + offset.x, j as f64 + 0.5 + offset.y);
+                    rays[lane] = self.get_ray(sampler, positions[lane]);
+                }
+
+                let ray_t = rays.each_ref().map(|ray| match ray {
+                    Some(_) => Interval::new(0.001, f64::INFINITY),
+                    None => Interval::EMPTY,
+                });
+                let packet =
+                    RayPacket::new(rays.each_ref().map(|ray| ray.clone().unwrap_or_default()));
+                let hits = world.hit_packet(&packet, &ray_t);
+
+                for (lane, hit) in hits.into_iter().enumerate().take(lanes.len()) {
+                    let sample = match &rays[lane] {
+                        Some(ray) => {
+                            self.integrator
+                                .sample_hit(&mut samplers[lane], ray, hit, world)
+                        }
+                        None => FilmSample::default(),
+                    };
+                    estimate.add(sample.direct + sample.indirect);
+                    film.add_sample((i, j), positions[lane], &sample);
                 }
-                let color: Color = (color * self.pixel_samples_scale).into();
-                pixels.push(color);
             }
         }
-        eprint!("\rDone.                   \n");
-        pixels
+        film
+    }
+
+    fn tiles(&self) -> Vec<Tile> {
+        let mut tiles = Vec::new();
+        for y0 in (0..self.image_height).step_by(TILE_SIZE as usize) {
+            for x0 in (0..self.image_width).step_by(TILE_SIZE as usize) {
+                tiles.push(Tile {
+                    x0,
+                    y0,
+                    x1: (x0 + TILE_SIZE).min(self.image_width),
+                    y1: (y0 + TILE_SIZE).min(self.image_height),
+                });
+            }
+        }
+        tiles
+    }
+
+    fn needs_samples(&self, estimate: &PixelEstimate) -> bool {
+        match self.adaptive_error {
+            Some(max_error) => {
+                estimate.samples() < self.min_samples_per_pixel
+                    || estimate.relative_error() > max_error
+            }
+            None => true,
+        }
+    }
+
+    /// Renders the image with tiles spread over the rayon thread pool. Every
+    /// random number is derived from `seed`, the same seed always gives the
+    /// same image whatever the number of threads.
+    pub fn render<M: Material>(
+        &self,
+        seed: u64,
+        world: &(impl Hittable<M> + Sync + ?Sized),
+    ) -> Film {
+        self.render_progressive(seed, world, |_| {})
+    }
+
+    /// Renders in passes of `samples_per_pass` samples, calling `on_pass` with
+    /// the image so far after each of them. Stops when `samples_per_pixel` is
+    /// reached, the time budget is spent, the variance threshold is met or
+    /// adaptive sampling has stopped every pixel, whichever comes first. With
+    /// adaptive sampling the pixels still too noisy at `samples_per_pixel`
+    /// carry on up to `max_samples_per_pixel`.
+    pub fn render_progressive<M: Material>(
+        &self,
+        seed: u64,
+        world: &(impl Hittable<M> + Sync + ?Sized),
+        mut on_pass: impl FnMut(&Progress),
+    ) -> Film {
+        let start = Instant::now();
+        let tiles = self.tiles();
+        let mut estimates: Vec<Vec<PixelEstimate>> = tiles
+            .iter()
+            .map(|tile| vec![PixelEstimate::default(); tile.len()])
+            .collect();
+
+        let mut film = Film::new(self.image_width, self.image_height);
+
+        let mut samples = 0;
+        let mut pass = 0;
+        loop {
+            let pass_samples =
+                samples..(samples + self.samples_per_pass).min(self.max_samples_per_pixel);
+            let film_tiles: Vec<FilmTile> = tiles
+                .par_iter()
+                .zip(estimates.par_iter_mut())
+                .map(|(tile, tile_estimates)| {
+                    self.render_tile(seed, world, tile, pass_samples.clone(), tile_estimates)
+                })
+                .collect();
+            // Merged in tile order so the sums don't depend on scheduling.
+            for film_tile in &film_tiles {
+                film.merge_tile(film_tile);
+            }
+            samples = pass_samples.end;
+            pass += 1;
+
+            let max_variance = estimates
+                .iter()
+                .flatten()
+                .map(PixelEstimate::variance_of_mean)
+                .fold(0.0, f64::max);
+            let active_pixels = estimates
+                .iter()
+                .flatten()
+                .filter(|estimate| self.needs_samples(estimate))
+                .count();
+            let image = film.image();
+            let sample_counts = film.sample_counts();
+            let progress = Progress {
+                pass,
+                samples_per_pixel: samples,
+                elapsed: start.elapsed(),
+                max_variance,
+                active_pixels,
+                image: &image,
+                sample_counts: &sample_counts,
+            };
+            eprint!(
+                "\rPass {pass}: {samples} spp, max variance {max_variance:.3e}, {active_pixels} active   "
+            );
+            on_pass(&progress);
+
+            let done = samples >= self.max_samples_per_pixel
+                || active_pixels == 0
+                || self
+                    .time_budget
+                    .is_some_and(|budget| progress.elapsed >= budget)
+                || self.variance_threshold.is_some_and(|t| max_variance < t);
+            if done {
+                eprint!("\rDone.                                          \n");
+                return film;
+            }
+        }
+    }
+}
+
+// Side of the square tiles the image is split into for rendering
+const TILE_SIZE: u32 = 16;
+
+/// Block of pixels [x0, x1) x [y0, y1).
+#[derive(Debug, Clone)]
+struct Tile {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+}
+
+impl Tile {
+    fn len(&self) -> usize {
+        ((self.x1 - self.x0) * (self.y1 - self.y0)) as usize
+    }
+
+    /// Pixel coordinates in scanline order.
+    fn pixels(&self) -> impl Iterator<Item = (u32, u32)> + use<> {
+        let (x0, x1) = (self.x0, self.x1);
+        (self.y0..self.y1).flat_map(move |j| (x0..x1).map(move |i| (i, j)))
     }
 }
@@ -1,5 +1,6 @@
 use std::{
     fmt,
+    io::{self, Write},
     ops::{AddAssign, Deref, DivAssign},
 };
 
@@ -50,10 +51,21 @@ impl Color {
     }
 
     pub fn output_pixels(pixels: Vec<Color>, width: u32, height: u32) {
-        print!("P3\n{} {}\n255\n", width, height);
+        Self::write_pixels(&mut io::stdout().lock(), &pixels, width, height)
+            .expect("failed to write image to stdout");
+    }
+
+    pub fn write_pixels(
+        out: &mut impl Write,
+        pixels: &[Color],
+        width: u32,
+        height: u32,
+    ) -> io::Result<()> {
+        write!(out, "P3\n{} {}\n255\n", width, height)?;
         for color in pixels {
-            println!("{color}");
+            writeln!(out, "{color}")?;
         }
+        Ok(())
     }
 }
 
@@ -0,0 +1,282 @@
+use rayon::prelude::*;
+
+use crate::{
+    color::Color,
+    film::{Film, Pass},
+    microfacet::luminance,
+    vec3::Vec3,
+};
+
+// B3 spline taps of the à-trous wavelet, spread further apart every iteration
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+
+// Albedo below which a channel is left modulated, avoiding division by zero
+const MIN_ALBEDO: f64 = 0.01;
+
+// Keeps noise free pixels from rejecting every neighbour
+const MIN_DEVIATION: f64 = 1e-4;
+
+// Smallest depth and albedo sigma, which keeps equal features from weighing
+// 0/0 when their noise is zero
+const MIN_SIGMA: f64 = 1e-6;
+
+// Feature variance at which the albedo of a pixel is mostly replaced by the
+// filtered one
+const FEATURE_NOISE: f64 = 0.02;
+
+/// Edge-avoiding à-trous wavelet filter, smoothing the beauty pass of a film
+/// without blurring across the edges found in its albedo, normal and depth
+/// passes, nor across lighting changes larger than the noise of each pixel.
+#[derive(Debug, Clone)]
+pub struct Denoiser {
+    iterations: u32,
+    color_sigma: f64,
+    normal_power: f64,
+    depth_sigma: f64,
+    albedo_sigma: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Self {
+            iterations: 3,
+            color_sigma: 2.0,
+            normal_power: 8.0,
+            depth_sigma: 0.05,
+            albedo_sigma: 0.1,
+        }
+    }
+}
+
+/// Per pixel guides of the filter.
+#[derive(Debug, Clone, Copy)]
+struct Features {
+    albedo: Vec3,
+    normal: Vec3,
+    // Infinite where nothing was hit
+    depth: f64,
+    // Variance of the mean albedo and normal
+    variance: f64,
+}
+
+/// Pixel being filtered.
+#[derive(Debug, Clone, Copy)]
+struct Texel {
+    // Lighting, the beauty divided by the albedo
+    irradiance: Vec3,
+    variance: f64,
+    // Filtered along with the lighting, to put back where defocus or motion
+    // blur left the pixel's own albedo noisy
+    albedo: Vec3,
+}
+
+impl Denoiser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of filter passes, each one reaching twice as far, so that the
+    /// last one spans 4 * 2^(iterations - 1) pixels on each side.
+    pub fn iterations(mut self, iterations: u32) -> Self {
+        self.iterations = iterations;
+        self
+    }
+
+    /// Luminance difference, in standard deviations of the noise of the two
+    /// pixels, at which neighbours stop counting.
+    pub fn color_sigma(mut self, sigma: f64) -> Self {
+        self.color_sigma = sigma;
+        self
+    }
+
+    /// Sharpness of the normal weight, about the exponent of the cosine
+    /// between normals, higher keeps creases sharper.
+    pub fn normal_power(mut self, power: f64) -> Self {
+        self.normal_power = power;
+        self
+    }
+
+    /// Depth difference, relative to the depth and per pixel of distance, at
+    /// which neighbours stop counting.
+    pub fn depth_sigma(mut self, sigma: f64) -> Self {
+        self.depth_sigma = sigma.max(MIN_SIGMA);
+        self
+    }
+
+    /// Albedo difference at which neighbours stop counting.
+    pub fn albedo_sigma(mut self, sigma: f64) -> Self {
+        self.albedo_sigma = sigma.max(MIN_SIGMA);
+        self
+    }
+
+    /// Denoised beauty pass of `film`.
+    pub fn denoise(&self, film: &Film) -> Vec<Color> {
+        let size = (film.width() as usize, film.height() as usize);
+        let vectors = |pass| -> Vec<Vec3> {
+            film.pass(pass)
+                .chunks_exact(3)
+                .map(|v| Vec3::new(v[0], v[1], v[2]))
+                .collect()
+        };
+        let features: Vec<Features> = vectors(Pass::Albedo)
+            .into_iter()
+            .zip(vectors(Pass::Normal))
+            .zip(film.pass(Pass::Depth))
+            .zip(film.pass(Pass::FeatureVariance))
+            .map(|(((albedo, normal), depth), variance)| Features {
+                albedo,
+                normal: if normal.length_squared() > 0.0 {
+                    normal.unit_vector()
+                } else {
+                    normal
+                },
+                depth,
+                variance,
+            })
+            .collect();
+
+        // Filter the lighting alone so that textures are not smeared, and put
+        // the albedo back at the end.
+        let mut current: Vec<Texel> = vectors(Pass::Beauty)
+            .iter()
+            .zip(film.pass(Pass::Variance))
+            .zip(&features)
+            .map(|((c, variance), f)| {
+                let channel = |a: f64| if a > MIN_ALBEDO { a } else { 1.0 };
+                let albedo = Vec3::new(
+                    channel(f.albedo.x),
+                    channel(f.albedo.y),
+                    channel(f.albedo.z),
+                );
+                let scale = luminance(&albedo);
+                Texel {
+                    irradiance: Vec3::new(c.x / albedo.x, c.y / albedo.y, c.z / albedo.z),
+                    variance: variance / (scale * scale),
+                    albedo,
+                }
+            })
+            .collect();
+        let modulation: Vec<Vec3> = current.iter().map(|t| t.albedo).collect();
+        let mut next = current.clone();
+
+        for iteration in 0..self.iterations {
+            let step = 1_i64 << iteration;
+            let variance: Vec<f64> = (0..current.len())
+                .into_par_iter()
+                .map(|p| blurred_variance(&current, p, size))
+                .collect();
+            next.par_chunks_mut(size.0)
+                .enumerate()
+                .for_each(|(y, row)| {
+                    for (x, out) in row.iter_mut().enumerate() {
+                        *out =
+                            self.filter_pixel(&current, &variance, &features, (x, y), size, step);
+                    }
+                });
+            std::mem::swap(&mut current, &mut next);
+        }
+
+        current
+            .iter()
+            .zip(&modulation)
+            .zip(&features)
+            .map(|((t, albedo), f)| {
+                let confidence = (-f.variance / FEATURE_NOISE).exp();
+                let albedo = *albedo * confidence + t.albedo * (1.0 - confidence);
+                (t.irradiance * albedo).into()
+            })
+            .collect()
+    }
+
+    /// One wavelet pass at pixel (x, y).
+    fn filter_pixel(
+        &self,
+        image: &[Texel],
+        variance: &[f64],
+        features: &[Features],
+        (x, y): (usize, usize),
+        (width, height): (usize, usize),
+        step: i64,
+    ) -> Texel {
+        let p = y * width + x;
+        let mut irradiance = Vec3::default();
+        let mut sum_variance = 0.0;
+        let mut albedo = Vec3::default();
+        let mut total = 0.0;
+        for (ky, hy) in KERNEL.iter().enumerate() {
+            let qy = y as i64 + (ky as i64 - 2) * step;
+            if qy < 0 || qy >= height as i64 {
+                continue;
+            }
+            for (kx, hx) in KERNEL.iter().enumerate() {
+                let qx = x as i64 + (kx as i64 - 2) * step;
+                if qx < 0 || qx >= width as i64 {
+                    continue;
+                }
+                let q = qy as usize * width + qx as usize;
+                // Measured against the noise of the difference, which unlike
+                // that of either pixel doesn't favour the one that happened
+                // to get the steadier samples.
+                let color_difference =
+                    (luminance(&image[p].irradiance) - luminance(&image[q].irradiance)).abs();
+                let color_scale =
+                    self.color_sigma * (variance[p] + variance[q]).sqrt() + MIN_DEVIATION;
+                let weight = hx
+                    * hy
+                    * (-color_difference / color_scale).exp()
+                    * self.feature_weight(&features[p], &features[q], step);
+                irradiance += image[q].irradiance * weight;
+                sum_variance += weight * weight * image[q].variance;
+                albedo += image[q].albedo * weight;
+                total += weight;
+            }
+        }
+        // The center tap always has a weight of at least 9/64.
+        Texel {
+            irradiance: irradiance / total,
+            variance: sum_variance / (total * total),
+            albedo: albedo / total,
+        }
+    }
+
+    /// Edge-stopping weight of the features, whose differences only count
+    /// past the noise defocus and motion blur leave in them.
+    fn feature_weight(&self, p: &Features, q: &Features, step: i64) -> f64 {
+        match (p.depth.is_finite(), q.depth.is_finite()) {
+            // Background only blends with background.
+            (false, false) => return 1.0,
+            (true, true) => {}
+            _ => return 0.0,
+        }
+
+        let noise = p.variance + q.variance;
+        let normal_difference = (p.normal - q.normal).length_squared();
+        // Close to the cosine between the normals to the power given
+        let normal = (-normal_difference / (2.0 / self.normal_power + noise)).exp();
+        let albedo_difference = (p.albedo - q.albedo).length_squared();
+        let albedo = (-albedo_difference / (self.albedo_sigma * self.albedo_sigma + noise)).exp();
+        let depth_scale = self.depth_sigma * step as f64 * p.depth.max(f64::EPSILON);
+        let depth = (-(p.depth - q.depth).abs() / depth_scale).exp();
+        normal * depth * albedo
+    }
+}
+
+/// Variance around pixel `p` smoothed by a 3x3 Gaussian, steadier than a
+/// single pixel's estimate.
+fn blurred_variance(image: &[Texel], p: usize, (width, height): (usize, usize)) -> f64 {
+    const TAPS: [f64; 3] = [0.25, 0.5, 0.25];
+    let (x, y) = ((p % width) as i64, (p / width) as i64);
+    let mut sum = 0.0;
+    let mut total = 0.0;
+    for (dy, wy) in TAPS.iter().enumerate() {
+        for (dx, wx) in TAPS.iter().enumerate() {
+            let (qx, qy) = (x + dx as i64 - 1, y + dy as i64 - 1);
+            if qx < 0 || qy < 0 || qx >= width as i64 || qy >= height as i64 {
+                continue;
+            }
+            sum += wx * wy * image[qy as usize * width + qx as usize].variance;
+            total += wx * wy;
+        }
+    }
+    sum / total
+}
@@ -0,0 +1,206 @@
+use std::io::{self, Write};
+
+// Pixel type of 32-bit float channels
+const FLOAT: i32 = 2;
+
+/// Writes a single part, scanline, uncompressed OpenEXR image of 32-bit
+/// float channels, each given as `width * height` values in scanline order.
+/// Channel names may carry a layer prefix such as `albedo.R`.
+pub fn write_exr(
+    out: &mut impl Write,
+    width: u32,
+    height: u32,
+    channels: &[(String, Vec<f32>)],
+) -> io::Result<()> {
+    let pixels = (width * height) as usize;
+    assert!(
+        channels.iter().all(|(_, values)| values.len() == pixels),
+        "every channel needs a value per pixel"
+    );
+
+    // Readers expect the channels sorted by name, in the list and the data.
+    let mut channels: Vec<&(String, Vec<f32>)> = channels.iter().collect();
+    channels.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&20000630_i32.to_le_bytes());
+    // Version 2, single part scanline
+    header.extend_from_slice(&2_i32.to_le_bytes());
+
+    let mut chlist = Vec::new();
+    for (name, _) in &channels {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&FLOAT.to_le_bytes());
+        // pLinear and reserved bytes, then x and y sampling
+        chlist.extend_from_slice(&[0; 4]);
+        chlist.extend_from_slice(&1_i32.to_le_bytes());
+        chlist.extend_from_slice(&1_i32.to_le_bytes());
+    }
+    chlist.push(0);
+    attribute(&mut header, "channels", "chlist", &chlist);
+
+    attribute(&mut header, "compression", "compression", &[0]);
+    let window = box2i(width, height);
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1.0_f32.to_le_bytes(),
+    );
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1.0_f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    // One block per scanline, each its y, its size, then every channel's
+    // values for the line.
+    let line_size = channels.len() * width as usize * 4;
+    let block_size = 8 + line_size;
+    let table_size = height as usize * 8;
+    let first_block = (header.len() + table_size) as u64;
+
+    out.write_all(&header)?;
+    for y in 0..height as u64 {
+        out.write_all(&(first_block + y * block_size as u64).to_le_bytes())?;
+    }
+
+    let mut block = Vec::with_capacity(block_size);
+    for y in 0..height as usize {
+        block.clear();
+        block.extend_from_slice(&(y as i32).to_le_bytes());
+        block.extend_from_slice(&(line_size as i32).to_le_bytes());
+        for (_, values) in &channels {
+            let line = &values[y * width as usize..(y + 1) * width as usize];
+            for value in line {
+                block.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        out.write_all(&block)?;
+    }
+    Ok(())
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend_from_slice(name.as_bytes());
+    header.push(0);
+    header.extend_from_slice(kind.as_bytes());
+    header.push(0);
+    header.extend_from_slice(&(value.len() as i32).to_le_bytes());
+    header.extend_from_slice(value);
+}
+
+fn box2i(width: u32, height: u32) -> Vec<u8> {
+    [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads a null terminated string at `pos`, moving past it.
+    fn string(data: &[u8], pos: &mut usize) -> String {
+        let end = *pos + data[*pos..].iter().position(|&b| b == 0).unwrap();
+        let s = String::from_utf8(data[*pos..end].to_vec()).unwrap();
+        *pos = end + 1;
+        s
+    }
+
+    fn int(data: &[u8], pos: &mut usize) -> i32 {
+        let value = i32::from_le_bytes(data[*pos..*pos + 4].try_into().unwrap());
+        *pos += 4;
+        value
+    }
+
+    #[test]
+    fn header_offsets_and_blocks_follow_the_layout() {
+        let (width, height) = (3, 2);
+        // Values tell apart the channel, the line and the pixel.
+        let channel = |c: f32| (0..6).map(|i| c * 100.0 + i as f32).collect();
+        let channels = [
+            ("depth.Z".to_string(), channel(1.0)),
+            ("B".to_string(), channel(2.0)),
+            ("albedo.R".to_string(), channel(3.0)),
+        ];
+        let mut data = Vec::new();
+        write_exr(&mut data, width, height, &channels).unwrap();
+
+        let mut pos = 0;
+        assert_eq!(int(&data, &mut pos), 20000630);
+        assert_eq!(int(&data, &mut pos), 2);
+
+        let mut attributes = Vec::new();
+        while data[pos] != 0 {
+            let name = string(&data, &mut pos);
+            let kind = string(&data, &mut pos);
+            let size = int(&data, &mut pos) as usize;
+            attributes.push((name, kind, data[pos..pos + size].to_vec()));
+            pos += size;
+        }
+        pos += 1;
+        let names: Vec<&str> = attributes.iter().map(|(name, ..)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "channels",
+                "compression",
+                "dataWindow",
+                "displayWindow",
+                "lineOrder",
+                "pixelAspectRatio",
+                "screenWindowCenter",
+                "screenWindowWidth",
+            ]
+        );
+
+        // Channels are listed sorted by name, each as 32-bit float sampled
+        // at every pixel.
+        let (_, kind, chlist) = &attributes[0];
+        assert_eq!(kind, "chlist");
+        let mut at = 0;
+        for expected in ["B", "albedo.R", "depth.Z"] {
+            assert_eq!(string(chlist, &mut at), expected);
+            assert_eq!(int(chlist, &mut at), FLOAT);
+            assert_eq!(int(chlist, &mut at), 0);
+            assert_eq!(int(chlist, &mut at), 1);
+            assert_eq!(int(chlist, &mut at), 1);
+        }
+        assert_eq!(&chlist[at..], [0]);
+        assert_eq!(attributes[1].2, [0]);
+        assert_eq!(attributes[2].2, box2i(width, height));
+        assert_eq!(attributes[2].2, attributes[3].2);
+
+        // The offset table points at one block per line, which hold the line
+        // of every channel in name order, and end the file.
+        let offsets: Vec<usize> = (0..height)
+            .map(|_| {
+                let offset = u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap());
+                pos += 8;
+                offset as usize
+            })
+            .collect();
+        for (y, &offset) in offsets.iter().enumerate() {
+            assert_eq!(offset, pos);
+            assert_eq!(int(&data, &mut pos), y as i32);
+            assert_eq!(int(&data, &mut pos), 3 * width as i32 * 4);
+            for c in [2.0, 3.0, 1.0] {
+                for x in 0..width as usize {
+                    let value = f32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+                    assert_eq!(value, c * 100.0 + (y * width as usize + x) as f32);
+                    pos += 4;
+                }
+            }
+        }
+        assert_eq!(pos, data.len());
+    }
+}
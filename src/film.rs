@@ -0,0 +1,419 @@
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+use crate::{
+    color::Color,
+    exr,
+    filter::Filter,
+    progressive::PixelEstimate,
+    vec3::{Point, Vec3},
+};
+
+/// Output passes of the film.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    Beauty,
+    /// Surface color at the first hit, or at the first rough surface seen
+    /// through mirrors and glass, tinted by them.
+    Albedo,
+    /// World space shading normal at the same hit as the albedo, facing the
+    /// ray.
+    Normal,
+    /// Distance to the nearest first hit within the pixel, infinite where
+    /// nothing was hit.
+    Depth,
+    /// World space position of the nearest first hit.
+    Position,
+    /// One plus the index of the nearest hit object in its scene list, zero
+    /// where nothing was hit.
+    ObjectId,
+    /// Materials of the nearest hits numbered from one in the order they
+    /// first appear in the image, zero where nothing was hit.
+    MaterialId,
+    /// Light reaching the camera straight from an emitter or the background,
+    /// or after a single bounce.
+    Direct,
+    /// Light reaching the camera after two or more bounces.
+    Indirect,
+    SampleCount,
+    /// Variance of the mean luminance of the pixel's own samples, zero until
+    /// it has two.
+    Variance,
+    /// Variance of the mean albedo and normal of the pixel's own samples,
+    /// high where defocus or motion blur mix several surfaces at random.
+    FeatureVariance,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 12] = [
+        Pass::Beauty,
+        Pass::Albedo,
+        Pass::Normal,
+        Pass::Depth,
+        Pass::Position,
+        Pass::ObjectId,
+        Pass::MaterialId,
+        Pass::Direct,
+        Pass::Indirect,
+        Pass::SampleCount,
+        Pass::Variance,
+        Pass::FeatureVariance,
+    ];
+
+    /// Channel names as written to EXR, the beauty pass being the default
+    /// layer.
+    pub fn channels(&self) -> &'static [&'static str] {
+        match self {
+            Pass::Beauty => &["R", "G", "B"],
+            Pass::Albedo => &["albedo.R", "albedo.G", "albedo.B"],
+            Pass::Normal => &["normal.X", "normal.Y", "normal.Z"],
+            Pass::Depth => &["depth.Z"],
+            Pass::Position => &["position.X", "position.Y", "position.Z"],
+            Pass::ObjectId => &["objectId.id"],
+            Pass::MaterialId => &["materialId.id"],
+            Pass::Direct => &["direct.R", "direct.G", "direct.B"],
+            Pass::Indirect => &["indirect.R", "indirect.G", "indirect.B"],
+            Pass::SampleCount => &["sampleCount.count"],
+            Pass::Variance => &["variance.Y"],
+            Pass::FeatureVariance => &["featureVariance.Y"],
+        }
+    }
+}
+
+/// First surface a camera sample hit.
+#[derive(Debug, Clone, Copy)]
+pub struct SurfaceSample {
+    pub albedo: Color,
+    pub normal: Vec3,
+    pub position: Point,
+    pub depth: f64,
+    pub object_id: u32,
+    // Address of the material, only compared for identity
+    pub material: usize,
+}
+
+/// Everything a single camera sample contributes to the film.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FilmSample {
+    pub direct: Vec3,
+    pub indirect: Vec3,
+    pub surface: Option<SurfaceSample>,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct FilmPixel {
+    // Filter weighted sums of the samples, and of their weights
+    direct: Vec3,
+    indirect: Vec3,
+    albedo: Vec3,
+    normal: Vec3,
+    weight: f64,
+}
+
+/// Pixel data that can't be filtered, taken from the nearest hit among the
+/// pixel's own samples.
+#[derive(Debug, Clone, Default)]
+struct PixelCoverage {
+    nearest: Option<SurfaceSample>,
+    estimate: PixelEstimate,
+    // Sums of the albedos and normals, and of their squared lengths
+    albedo: Vec3,
+    normal: Vec3,
+    features_squared: f64,
+}
+
+impl PixelCoverage {
+    fn samples(&self) -> u32 {
+        self.estimate.samples()
+    }
+
+    fn variance_of_mean(&self) -> f64 {
+        if self.samples() < 2 {
+            return 0.0;
+        }
+        self.estimate.variance_of_mean()
+    }
+
+    fn feature_variance(&self) -> f64 {
+        if self.samples() < 2 {
+            return 0.0;
+        }
+        let n = self.samples() as f64;
+        let mean_squared = (self.albedo / n).length_squared() + (self.normal / n).length_squared();
+        let variance = (self.features_squared - n * mean_squared) / (n - 1.0);
+        variance.max(0.0) / n
+    }
+
+    fn merge(&mut self, other: &PixelCoverage) {
+        self.estimate.merge(&other.estimate);
+        self.albedo += other.albedo;
+        self.normal += other.normal;
+        self.features_squared += other.features_squared;
+        if let Some(surface) = other.nearest
+            && self
+                .nearest
+                .is_none_or(|nearest| surface.depth < nearest.depth)
+        {
+            self.nearest = Some(surface);
+        }
+    }
+}
+
+/// Pixel range a sample at continuous coordinate `x` contributes to, pixel
+/// `k` being centered on k + 0.5.
+fn filter_extent(filter: &Filter, x: f64) -> (i64, i64) {
+    let radius = filter.radius();
+    (
+        (x - radius - 0.5).floor() as i64 + 1,
+        (x + radius - 0.5).floor() as i64,
+    )
+}
+
+/// Image accumulating filtered samples, each one spread over the pixels
+/// within the filter radius, along with the other output passes.
+#[derive(Debug, Clone)]
+pub struct Film {
+    width: u32,
+    height: u32,
+    pixels: Vec<FilmPixel>,
+    coverage: Vec<PixelCoverage>,
+}
+
+impl Film {
+    pub fn new(width: u32, height: u32) -> Self {
+        let len = (width * height) as usize;
+        Self {
+            width,
+            height,
+            pixels: vec![FilmPixel::default(); len],
+            coverage: vec![PixelCoverage::default(); len],
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Adds the samples of a tile, the parts falling outside the image being
+    /// dropped.
+    pub fn merge_tile(&mut self, tile: &FilmTile) {
+        for (row, y) in (tile.y0..tile.y0 + tile.height as i64).enumerate() {
+            if y < 0 || y >= self.height as i64 {
+                continue;
+            }
+            for (col, x) in (tile.x0..tile.x0 + tile.width as i64).enumerate() {
+                if x < 0 || x >= self.width as i64 {
+                    continue;
+                }
+                let src = &tile.pixels[row * tile.width + col];
+                let dst = &mut self.pixels[(y * self.width as i64 + x) as usize];
+                dst.direct += src.direct;
+                dst.indirect += src.indirect;
+                dst.albedo += src.albedo;
+                dst.normal += src.normal;
+                dst.weight += src.weight;
+            }
+        }
+
+        for (row, y) in (tile.core.1..tile.core.3).enumerate() {
+            for (col, x) in (tile.core.0..tile.core.2).enumerate() {
+                let width = (tile.core.2 - tile.core.0) as usize;
+                let src = &tile.coverage[row * width + col];
+                self.coverage[(y * self.width + x) as usize].merge(src);
+            }
+        }
+    }
+
+    /// Filtered beauty pass.
+    pub fn image(&self) -> Vec<Color> {
+        self.pixels
+            .iter()
+            .map(|pixel| Self::filtered(pixel, pixel.direct + pixel.indirect).into())
+            .collect()
+    }
+
+    /// Samples taken by each pixel so far.
+    pub fn sample_counts(&self) -> Vec<u32> {
+        self.coverage.iter().map(PixelCoverage::samples).collect()
+    }
+
+    // Weighted average, black where no sample has any weight
+    fn filtered(pixel: &FilmPixel, sum: Vec3) -> Vec3 {
+        if pixel.weight > 0.0 {
+            sum / pixel.weight
+        } else {
+            Vec3::default()
+        }
+    }
+
+    /// Values of `pass` in scanline order, `pass.channels().len()` of them
+    /// per pixel.
+    pub fn pass(&self, pass: Pass) -> Vec<f64> {
+        let filtered = |value: fn(&FilmPixel) -> Vec3| -> Vec<f64> {
+            self.pixels
+                .iter()
+                .flat_map(|pixel| {
+                    let v = Self::filtered(pixel, value(pixel));
+                    [v.x, v.y, v.z]
+                })
+                .collect()
+        };
+        let nearest = |value: fn(&SurfaceSample) -> f64, missed: f64| -> Vec<f64> {
+            self.coverage
+                .iter()
+                .map(|c| c.nearest.as_ref().map_or(missed, value))
+                .collect()
+        };
+
+        match pass {
+            Pass::Beauty => filtered(|p| p.direct + p.indirect),
+            Pass::Albedo => filtered(|p| p.albedo),
+            Pass::Normal => filtered(|p| p.normal),
+            Pass::Direct => filtered(|p| p.direct),
+            Pass::Indirect => filtered(|p| p.indirect),
+            Pass::Depth => nearest(|s| s.depth, f64::INFINITY),
+            Pass::Position => self
+                .coverage
+                .iter()
+                .flat_map(|c| {
+                    let p = c.nearest.map_or(Point::default(), |s| s.position);
+                    [p.x, p.y, p.z]
+                })
+                .collect(),
+            Pass::ObjectId => nearest(|s| s.object_id as f64 + 1.0, 0.0),
+            Pass::MaterialId => {
+                let mut ids = HashMap::new();
+                self.coverage
+                    .iter()
+                    .map(|c| match c.nearest {
+                        Some(s) => {
+                            let next = ids.len() + 1;
+                            *ids.entry(s.material).or_insert(next) as f64
+                        }
+                        None => 0.0,
+                    })
+                    .collect()
+            }
+            Pass::SampleCount => self.coverage.iter().map(|c| c.samples() as f64).collect(),
+            Pass::Variance => self
+                .coverage
+                .iter()
+                .map(PixelCoverage::variance_of_mean)
+                .collect(),
+            Pass::FeatureVariance => self
+                .coverage
+                .iter()
+                .map(PixelCoverage::feature_variance)
+                .collect(),
+        }
+    }
+
+    /// Writes every pass as a layer of a multi-layer OpenEXR image.
+    pub fn write_exr(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut channels = Vec::new();
+        for pass in Pass::ALL {
+            let names = pass.channels();
+            let values = self.pass(pass);
+            for (c, name) in names.iter().enumerate() {
+                let channel = values
+                    .iter()
+                    .skip(c)
+                    .step_by(names.len())
+                    .map(|&v| v as f32)
+                    .collect();
+                channels.push((name.to_string(), channel));
+            }
+        }
+        exr::write_exr(out, self.width, self.height, &channels)
+    }
+}
+
+/// Private film of a block of pixels, extended by the reach of the filter so
+/// tiles can be rendered in parallel and merged afterwards.
+#[derive(Debug, Clone)]
+pub struct FilmTile {
+    filter: Filter,
+    x0: i64,
+    y0: i64,
+    width: usize,
+    height: usize,
+    pixels: Vec<FilmPixel>,
+    // Block the samples are taken in, as x0, y0, x1, y1
+    core: (u32, u32, u32, u32),
+    coverage: Vec<PixelCoverage>,
+}
+
+impl FilmTile {
+    /// Film for samples taken within pixels [x0, x1) x [y0, y1).
+    pub fn new(filter: Filter, x0: u32, y0: u32, x1: u32, y1: u32) -> Self {
+        let (min_x, _) = filter_extent(&filter, x0 as f64);
+        let (min_y, _) = filter_extent(&filter, y0 as f64);
+        let (_, max_x) = filter_extent(&filter, x1 as f64);
+        let (_, max_y) = filter_extent(&filter, y1 as f64);
+        let width = (max_x - min_x + 1).max(0) as usize;
+        let height = (max_y - min_y + 1).max(0) as usize;
+
+        Self {
+            filter,
+            x0: min_x,
+            y0: min_y,
+            width,
+            height,
+            pixels: vec![FilmPixel::default(); width * height],
+            core: (x0, y0, x1, y1),
+            coverage: vec![PixelCoverage::default(); ((x1 - x0) * (y1 - y0)) as usize],
+        }
+    }
+
+    /// Splats a sample of pixel (i, j) taken at continuous image position
+    /// (x, y).
+    pub fn add_sample(&mut self, (i, j): (u32, u32), (x, y): (f64, f64), sample: &FilmSample) {
+        let (albedo, normal) = sample
+            .surface
+            .map_or((Vec3::default(), Vec3::default()), |s| {
+                (*s.albedo, s.normal)
+            });
+
+        let (min_x, max_x) = filter_extent(&self.filter, x);
+        let (min_y, max_y) = filter_extent(&self.filter, y);
+        let min_x = min_x.max(self.x0);
+        let min_y = min_y.max(self.y0);
+        let max_x = max_x.min(self.x0 + self.width as i64 - 1);
+        let max_y = max_y.min(self.y0 + self.height as i64 - 1);
+
+        for py in min_y..=max_y {
+            let wy = self.filter.evaluate_1d(y - (py as f64 + 0.5));
+            if wy == 0.0 {
+                continue;
+            }
+            let row = ((py - self.y0) as usize) * self.width;
+            for px in min_x..=max_x {
+                let weight = wy * self.filter.evaluate_1d(x - (px as f64 + 0.5));
+                let pixel = &mut self.pixels[row + (px - self.x0) as usize];
+                pixel.direct += sample.direct * weight;
+                pixel.indirect += sample.indirect * weight;
+                pixel.albedo += albedo * weight;
+                pixel.normal += normal * weight;
+                pixel.weight += weight;
+            }
+        }
+
+        let (x0, y0, x1, _) = self.core;
+        let index = ((j - y0) * (x1 - x0) + (i - x0)) as usize;
+        let mut estimate = PixelEstimate::default();
+        estimate.add(sample.direct + sample.indirect);
+        self.coverage[index].merge(&PixelCoverage {
+            nearest: sample.surface,
+            estimate,
+            albedo,
+            normal,
+            features_squared: albedo.length_squared() + normal.length_squared(),
+        });
+    }
+}
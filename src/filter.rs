@@ -0,0 +1,84 @@
+use std::f64::consts::PI;
+
+/// Pixel reconstruction filter, weighting each sample by its offset from the
+/// pixel center. All filters are separable and `radius` is in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Filter {
+    /// Plain average, radius 0.5 keeps every sample to its own pixel.
+    Box { radius: f64 },
+    /// Linear falloff to zero at the radius.
+    Tent { radius: f64 },
+    /// Gaussian of standard deviation `sigma`, shifted to reach zero at the
+    /// radius.
+    Gaussian { radius: f64, sigma: f64 },
+    /// Mitchell-Netravali cubic, B = C = 1/3 being the usual compromise
+    /// between blurring and ringing.
+    Mitchell { radius: f64, b: f64, c: f64 },
+    /// Sinc windowed by a wider sinc, reaching zero at the radius.
+    Lanczos { radius: f64 },
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::Box { radius: 0.5 }
+    }
+}
+
+impl Filter {
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Filter::Box { radius }
+            | Filter::Tent { radius }
+            | Filter::Gaussian { radius, .. }
+            | Filter::Mitchell { radius, .. }
+            | Filter::Lanczos { radius } => radius,
+        }
+    }
+
+    /// Weight of a sample at offset (x, y) from the pixel center. Mitchell and
+    /// Lanczos have negative lobes.
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    pub fn evaluate_1d(&self, x: f64) -> f64 {
+        let radius = self.radius();
+        // Half open so that a sample on the border of two box filtered pixels
+        // counts once.
+        if x < -radius || x >= radius {
+            return 0.0;
+        }
+
+        match *self {
+            Filter::Box { .. } => 1.0,
+            Filter::Tent { radius } => radius - x.abs(),
+            Filter::Gaussian { radius, sigma } => {
+                let gaussian = |x: f64| (-x * x / (2.0 * sigma * sigma)).exp();
+                (gaussian(x) - gaussian(radius)).max(0.0)
+            }
+            Filter::Mitchell { radius, b, c } => {
+                let x = (2.0 * x / radius).abs();
+                if x > 1.0 {
+                    ((-b - 6.0 * c) * x * x * x
+                        + (6.0 * b + 30.0 * c) * x * x
+                        + (-12.0 * b - 48.0 * c) * x
+                        + (8.0 * b + 24.0 * c))
+                        / 6.0
+                } else {
+                    ((12.0 - 9.0 * b - 6.0 * c) * x * x * x
+                        + (-18.0 + 12.0 * b + 6.0 * c) * x * x
+                        + (6.0 - 2.0 * b))
+                        / 6.0
+                }
+            }
+            Filter::Lanczos { radius } => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        return 1.0;
+    }
+    (PI * x).sin() / (PI * x)
+}
@@ -1,6 +1,7 @@
 use crate::{
     aabb::AABB,
     interval::Interval,
+    packet::{PACKET_WIDTH, RayPacket},
     ray::Ray,
     vec3::{Point, Vec3},
 };
@@ -9,12 +10,29 @@ pub struct HitRecord<'a, T> {
     pub p: Point,
     pub normal: Vec3,
     pub t: f64,
+    // Surface coordinates of the hit point
+    pub u: f64,
+    pub v: f64,
+    // Partial derivatives of the surface position along u and v
+    pub dpdu: Vec3,
+    pub dpdv: Vec3,
     pub front_face: bool,
     pub material: &'a T,
+    // Index of the hit object in the scene list it came from
+    pub object_id: u32,
+    // Time of the ray, at which animated materials are evaluated
+    pub time: f64,
 }
 
 impl<'a, T> HitRecord<'a, T> {
-    pub fn new(ray: &Ray, p: Point, t: f64, outward_normal: Vec3, mat: &'a T) -> Self {
+    pub fn new(
+        ray: &Ray,
+        p: Point,
+        t: f64,
+        outward_normal: Vec3,
+        (u, v): (f64, f64),
+        mat: &'a T,
+    ) -> Self {
         let front_face = ray.dir.dot(&outward_normal) < 0.0;
         let normal = if front_face {
             outward_normal
@@ -26,26 +44,76 @@ impl<'a, T> HitRecord<'a, T> {
             p,
             normal,
             t,
+            u,
+            v,
+            dpdu: Vec3::default(),
+            dpdv: Vec3::default(),
             front_face,
             material: mat,
+            object_id: 0,
+            time: ray.time,
         }
     }
+
+    /// Replaces the shading normal, keeping it on the side the ray came from.
+    pub fn set_shading_normal(&mut self, outward_normal: Vec3) {
+        self.normal = if self.front_face {
+            outward_normal
+        } else {
+            -outward_normal
+        };
+    }
 }
 
 pub trait Hittable<T> {
-    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<T>>;
+    /// Returns the closest intersection within `ray_t`. Intersections cut out
+    /// by an alpha mask are not reported, the ray carries on past them, so
+    /// callers can keep narrowing the interval with the returned `t`.
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_, T>>;
 
     fn bounding_box(&self) -> AABB;
+
+    /// Whether anything blocks `ray` within `ray_t`. Unlike `hit` it can stop
+    /// at the first intersection found, which is all shadow rays need.
+    fn occluded(&self, ray: &Ray, ray_t: Interval) -> bool {
+        self.hit(ray, ray_t).is_some()
+    }
+
+    /// `hit` for every ray of `packet`, each within its own interval. Lanes
+    /// given an empty interval, as those of rays that missed an enclosing
+    /// box are, never hit anything.
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_t: &[Interval; PACKET_WIDTH],
+    ) -> [Option<HitRecord<'_, T>>; PACKET_WIDTH] {
+        std::array::from_fn(|lane| {
+            let interval = ray_t[lane];
+            if interval.min > interval.max {
+                return None;
+            }
+            self.hit(&packet.rays[lane], interval)
+        })
+    }
+
+    /// Bounding box and primitive tests `hit` takes for the same query, to
+    /// find where acceleration structures do poorly.
+    fn traversal_steps(&self, _ray: &Ray, _ray_t: Interval) -> u32 {
+        1
+    }
 }
 
-impl<T: Hittable<M>, M> Hittable<M> for &[T] {
-    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<M>> {
+impl<T: Hittable<M>, M> Hittable<M> for [T] {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_, M>> {
         let mut hit: Option<HitRecord<M>> = None;
         let mut closest_so_far = ray_t.max;
 
-        for hittable in self.iter() {
-            if let Some(hit_record) = hittable.hit(ray, Interval::new(ray_t.min, closest_so_far)) {
+        for (id, hittable) in self.iter().enumerate() {
+            if let Some(mut hit_record) =
+                hittable.hit(ray, Interval::new(ray_t.min, closest_so_far))
+            {
                 closest_so_far = hit_record.t;
+                hit_record.object_id = id as u32;
                 hit = Some(hit_record);
             }
         }
@@ -53,9 +121,100 @@ impl<T: Hittable<M>, M> Hittable<M> for &[T] {
         hit
     }
 
+    fn occluded(&self, ray: &Ray, ray_t: Interval) -> bool {
+        self.iter().any(|hittable| hittable.occluded(ray, ray_t))
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_t: &[Interval; PACKET_WIDTH],
+    ) -> [Option<HitRecord<'_, M>>; PACKET_WIDTH] {
+        let mut hits: [Option<HitRecord<M>>; PACKET_WIDTH] = Default::default();
+        let mut closest_so_far = *ray_t;
+
+        for (id, hittable) in self.iter().enumerate() {
+            let lanes = hittable.hit_packet(packet, &closest_so_far);
+            for (lane, hit) in lanes.into_iter().enumerate() {
+                if let Some(mut hit_record) = hit {
+                    closest_so_far[lane].max = hit_record.t;
+                    hit_record.object_id = id as u32;
+                    hits[lane] = Some(hit_record);
+                }
+            }
+        }
+
+        hits
+    }
+
     fn bounding_box(&self) -> AABB {
-        self.iter().fold(AABB::default(), |acc, elem| {
+        self.iter().fold(AABB::EMPTY, |acc, elem| {
             AABB::new_from_boxes(&acc, &elem.bounding_box())
         })
     }
+
+    fn traversal_steps(&self, ray: &Ray, ray_t: Interval) -> u32 {
+        let mut steps = 0;
+        let mut closest_so_far = ray_t.max;
+        for hittable in self {
+            let interval = Interval::new(ray_t.min, closest_so_far);
+            steps += hittable.traversal_steps(ray, interval);
+            if let Some(hit_record) = hittable.hit(ray, interval) {
+                closest_so_far = hit_record.t;
+            }
+        }
+        steps
+    }
+}
+
+impl<T: Hittable<M>, M> Hittable<M> for &[T] {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_, M>> {
+        (**self).hit(ray, ray_t)
+    }
+
+    fn occluded(&self, ray: &Ray, ray_t: Interval) -> bool {
+        (**self).occluded(ray, ray_t)
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_t: &[Interval; PACKET_WIDTH],
+    ) -> [Option<HitRecord<'_, M>>; PACKET_WIDTH] {
+        (**self).hit_packet(packet, ray_t)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        (**self).bounding_box()
+    }
+
+    fn traversal_steps(&self, ray: &Ray, ray_t: Interval) -> u32 {
+        (**self).traversal_steps(ray, ray_t)
+    }
+}
+
+impl<T: Hittable<M>, M> Hittable<M> for Vec<T> {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_, M>> {
+        self.as_slice().hit(ray, ray_t)
+    }
+
+    fn occluded(&self, ray: &Ray, ray_t: Interval) -> bool {
+        self.as_slice().occluded(ray, ray_t)
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_t: &[Interval; PACKET_WIDTH],
+    ) -> [Option<HitRecord<'_, M>>; PACKET_WIDTH] {
+        self.as_slice().hit_packet(packet, ray_t)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.as_slice().bounding_box()
+    }
+
+    fn traversal_steps(&self, ray: &Ray, ray_t: Interval) -> u32 {
+        self.as_slice().traversal_steps(ray, ray_t)
+    }
 }
@@ -0,0 +1,691 @@
+use std::{
+    ops::{Add, Mul},
+    sync::Arc,
+};
+
+use crate::{
+    bdpt::Bidirectional,
+    color::Color,
+    film::{FilmSample, SurfaceSample},
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    material::Material,
+    onb::Onb,
+    photon::PhotonMap,
+    progressive::heatmap_color,
+    ray::Ray,
+    sampler::Sampler,
+    spectrum::{N_SPECTRUM_SAMPLES, SampledSpectrum},
+    vec3::Vec3,
+};
+
+/// Estimates the light arriving along camera rays.
+pub trait Integrator {
+    /// Light carried back along `ray`, with the surface it first hits.
+    fn sample<M: Material>(
+        &self,
+        sampler: &mut impl Sampler,
+        ray: &Ray,
+        world: &(impl Hittable<M> + ?Sized),
+    ) -> FilmSample {
+        let hit = world.hit(ray, Interval::new(0.001, f64::INFINITY));
+        self.sample_hit(sampler, ray, hit, world)
+    }
+
+    /// Same as `sample`, with the first hit of `ray` already found, as it is
+    /// for camera rays traced in packets.
+    fn sample_hit<'w, M: Material>(
+        &self,
+        sampler: &mut impl Sampler,
+        ray: &Ray,
+        hit: Option<HitRecord<'w, M>>,
+        world: &'w (impl Hittable<M> + ?Sized),
+    ) -> FilmSample;
+}
+
+/// Any of the integrators, the path tracer by default.
+#[derive(Debug, Clone)]
+pub enum IntegratorKind {
+    Path(PathTracer),
+    Direct(DirectLighting),
+    AmbientOcclusion(AmbientOcclusion),
+    Normals(Normals),
+    Bidirectional(Bidirectional),
+    Uv(Uv),
+    Depth(Depth),
+    TraversalSteps(TraversalSteps),
+    ObjectId(ObjectId),
+}
+
+impl Default for IntegratorKind {
+    fn default() -> Self {
+        IntegratorKind::Path(PathTracer::default())
+    }
+}
+
+impl Integrator for IntegratorKind {
+    fn sample_hit<'w, M: Material>(
+        &self,
+        sampler: &mut impl Sampler,
+        ray: &Ray,
+        hit: Option<HitRecord<'w, M>>,
+        world: &'w (impl Hittable<M> + ?Sized),
+    ) -> FilmSample {
+        match self {
+            IntegratorKind::Path(i) => i.sample_hit(sampler, ray, hit, world),
+            IntegratorKind::Direct(i) => i.sample_hit(sampler, ray, hit, world),
+            IntegratorKind::AmbientOcclusion(i) => i.sample_hit(sampler, ray, hit, world),
+            IntegratorKind::Normals(i) => i.sample_hit(sampler, ray, hit, world),
+            IntegratorKind::Bidirectional(i) => i.sample_hit(sampler, ray, hit, world),
+            IntegratorKind::Uv(i) => i.sample_hit(sampler, ray, hit, world),
+            IntegratorKind::Depth(i) => i.sample_hit(sampler, ray, hit, world),
+            IntegratorKind::TraversalSteps(i) => i.sample_hit(sampler, ray, hit, world),
+            IntegratorKind::ObjectId(i) => i.sample_hit(sampler, ray, hit, world),
+        }
+    }
+}
+
+/// Quantity carried along a path, RGB triples or sampled spectra.
+trait PathRadiance: Copy + Add<Output = Self> + Mul<Output = Self> + Mul<f64, Output = Self> {
+    fn zero() -> Self;
+
+    fn one() -> Self;
+
+    fn max_value(&self) -> f64;
+
+    /// Converts an RGB emission or reflectance for the wavelengths of `ray`.
+    fn from_rgb(rgb: &Color, ray: &Ray) -> Self;
+
+    /// Linear RGB of the light gathered for the camera ray `ray`.
+    fn to_rgb(&self, ray: &Ray) -> Vec3;
+
+    /// Throughput factor of a scattering event turning `ray_in` into
+    /// `scattered` with the given RGB attenuation.
+    fn attenuation(rgb: &Color, ray_in: &Ray, _scattered: &Ray) -> Self {
+        Self::from_rgb(rgb, ray_in)
+    }
+}
+
+impl PathRadiance for Vec3 {
+    fn zero() -> Self {
+        Vec3::default()
+    }
+
+    fn one() -> Self {
+        Vec3::new(1.0, 1.0, 1.0)
+    }
+
+    fn max_value(&self) -> f64 {
+        self.x.max(self.y).max(self.z)
+    }
+
+    fn from_rgb(rgb: &Color, _ray: &Ray) -> Self {
+        **rgb
+    }
+
+    fn to_rgb(&self, _ray: &Ray) -> Vec3 {
+        *self
+    }
+}
+
+impl PathRadiance for SampledSpectrum {
+    fn zero() -> Self {
+        SampledSpectrum::new(0.0)
+    }
+
+    fn one() -> Self {
+        SampledSpectrum::new(1.0)
+    }
+
+    fn max_value(&self) -> f64 {
+        SampledSpectrum::max_value(self)
+    }
+
+    fn from_rgb(rgb: &Color, ray: &Ray) -> Self {
+        let lambda = ray.lambda.expect("spectral rays carry wavelengths");
+        SampledSpectrum::from_rgb(rgb, &lambda)
+    }
+
+    fn to_rgb(&self, ray: &Ray) -> Vec3 {
+        let lambda = ray.lambda.expect("spectral rays carry wavelengths");
+        SampledSpectrum::to_rgb(self, &lambda)
+    }
+
+    fn attenuation(rgb: &Color, ray_in: &Ray, scattered: &Ray) -> Self {
+        let lambda = ray_in.lambda.expect("spectral rays carry wavelengths");
+        let mut attenuation = SampledSpectrum::from_rgb(rgb, &lambda);
+        let scattered_lambda = scattered.lambda.expect("scatter keeps wavelengths");
+        if scattered_lambda.secondary_terminated() && !lambda.secondary_terminated() {
+            // From here on the hero wavelength carries the whole estimate.
+            let mut hero_only = [0.0; N_SPECTRUM_SAMPLES];
+            hero_only[0] = N_SPECTRUM_SAMPLES as f64;
+            attenuation *= SampledSpectrum::from(hero_only);
+        }
+        attenuation
+    }
+}
+
+/// Light gathered by a camera path, split into direct and indirect.
+struct PathResult<R> {
+    direct: R,
+    indirect: R,
+    surface: Option<SurfaceSample>,
+}
+
+impl<R: PathRadiance> PathResult<R> {
+    fn new() -> Self {
+        Self {
+            direct: R::zero(),
+            indirect: R::zero(),
+            surface: None,
+        }
+    }
+
+    /// Film sample of the path started by the camera ray `ray`, whose
+    /// wavelengths the spectra are measured at.
+    fn film_sample(&self, ray: &Ray) -> FilmSample {
+        FilmSample {
+            direct: self.direct.to_rgb(ray),
+            indirect: self.indirect.to_rgb(ray),
+            surface: self.surface,
+        }
+    }
+}
+
+/// Guides of the film passes at the hit `h` of `ray`.
+pub fn surface_sample<M: Material>(h: &HitRecord<M>, ray: &Ray) -> SurfaceSample {
+    SurfaceSample {
+        albedo: h.material.albedo(h),
+        normal: h.normal,
+        position: h.p,
+        depth: h.t * ray.dir.length(),
+        object_id: h.object_id,
+        material: h.material as *const M as usize,
+    }
+}
+
+/// Film sample showing `color` for the first surface hit `h` of `ray`.
+fn debug_sample<M: Material>(h: &HitRecord<M>, ray: &Ray, color: Vec3) -> FilmSample {
+    FilmSample {
+        direct: color,
+        indirect: Vec3::default(),
+        surface: Some(surface_sample(h, ray)),
+    }
+}
+
+/// Sky seen by rays leaving the scene, the only light of most scenes.
+pub fn background(ray: &Ray) -> Vec3 {
+    let unit_direction = ray.dir.unit_vector();
+    let a = 0.5 * (unit_direction.y + 1.0);
+
+    let white = Vec3::new(1.0, 1.0, 1.0);
+    let blue = Vec3::new(0.5, 0.7, 1.0);
+
+    white * (1.0 - a) + blue * a
+}
+
+/// Unidirectional path tracer, following a single scattered ray per bounce
+/// with the throughput kept along the way.
+#[derive(Debug, Clone)]
+pub struct PathTracer {
+    max_depth: u32,
+    roulette_depth: u32,
+    caustics: Option<Arc<PhotonMap>>,
+}
+
+impl Default for PathTracer {
+    fn default() -> Self {
+        Self {
+            max_depth: 10,
+            roulette_depth: 3,
+            caustics: None,
+        }
+    }
+}
+
+impl PathTracer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Hard cap on the number of bounces, paths mostly ending earlier by
+    /// Russian roulette.
+    pub fn max_depth(mut self, depth: u32) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Bounces after which a path carrying little light is randomly ended,
+    /// the survivors being weighted up so that the image stays unbiased.
+    /// `u32::MAX` turns Russian roulette off.
+    pub fn roulette_depth(mut self, depth: u32) -> Self {
+        self.roulette_depth = depth;
+        self
+    }
+
+    /// Takes the caustics seen on the first rough surface of a path, light
+    /// from the lights of `photons` that went through mirrors or glass to
+    /// get there, from the photon map instead of tracing them.
+    pub fn caustics(mut self, photons: PhotonMap) -> Self {
+        self.caustics = Some(Arc::new(photons));
+        self
+    }
+
+    fn trace<'w, M: Material, R: PathRadiance>(
+        &self,
+        sampler: &mut impl Sampler,
+        ray: &Ray,
+        hit: Option<HitRecord<'w, M>>,
+        world: &'w (impl Hittable<M> + ?Sized),
+    ) -> PathResult<R> {
+        let mut result = PathResult::new();
+        let mut throughput = R::one();
+        let mut ray = ray.clone();
+        // Product of the attenuations while only specular surfaces were hit,
+        // the surface albedo and normal being taken from the first rough one
+        // seen through them.
+        let mut specular_tint = Some(Vec3::new(1.0, 1.0, 1.0));
+        // Set from the rough surface caustics were gathered at while only
+        // mirrors and glass follow it, true once one of them was hit. Lights
+        // reached then are already in the photon map.
+        let mut caustic_path: Option<bool> = None;
+        let mut gathered = false;
+
+        let mut first_hit = Some(hit);
+
+        // Past the bounce limit no more light is gathered.
+        for bounce in 0..self.max_depth {
+            let hit = first_hit
+                .take()
+                .unwrap_or_else(|| world.hit(&ray, Interval::new(0.001, f64::INFINITY)));
+            let (light, scattered) = match hit {
+                Some(h) => {
+                    if bounce == 0 {
+                        result.surface = Some(surface_sample(&h, &ray));
+                    } else if let (Some(tint), Some(surface)) = (specular_tint, &mut result.surface)
+                    {
+                        surface.albedo = (tint * *h.material.albedo(&h)).into();
+                        surface.normal = h.normal;
+                    }
+
+                    let mut emitted = throughput * R::from_rgb(&h.material.emitted(&h), &ray);
+                    if let Some(photons) = &self.caustics {
+                        if caustic_path == Some(true) && photons.emits(h.object_id) {
+                            emitted = R::zero();
+                        }
+                        caustic_path = caustic_path
+                            .filter(|_| h.material.is_specular())
+                            .map(|_| true);
+                        if !gathered && !h.material.is_specular() {
+                            gathered = true;
+                            let wo = -ray.dir.unit_vector();
+                            if let Some(caustic) = photons.radiance(&h, &wo) {
+                                result.indirect =
+                                    result.indirect + throughput * R::from_rgb(&caustic, &ray);
+                                caustic_path = Some(false);
+                            }
+                        }
+                    }
+                    let scatter = h.material.scatter(sampler, &ray, &h);
+                    specular_tint = specular_tint
+                        .filter(|_| h.material.is_specular())
+                        .and_then(|tint| Some(tint * *scatter.as_ref()?.attenuation));
+                    if let Some(s) = &scatter {
+                        throughput =
+                            throughput * R::attenuation(&s.attenuation, &ray, &s.scattered);
+                    }
+                    (emitted, scatter.map(|s| s.scattered))
+                }
+                None => (
+                    throughput * R::from_rgb(&background(&ray).into(), &ray),
+                    None,
+                ),
+            };
+
+            if bounce <= 1 {
+                result.direct = result.direct + light;
+            } else {
+                result.indirect = result.indirect + light;
+            }
+            match scattered {
+                Some(scattered) => ray = scattered,
+                None => break,
+            }
+
+            // Paths carrying little light are ended at random, the survivors
+            // making up for them.
+            if bounce + 1 >= self.roulette_depth {
+                let survival = throughput.max_value();
+                if survival < 1.0 {
+                    if sampler.get_1d() >= survival {
+                        break;
+                    }
+                    throughput = throughput * (1.0 / survival);
+                }
+            }
+        }
+
+        result
+    }
+}
+
+impl Integrator for PathTracer {
+    fn sample_hit<'w, M: Material>(
+        &self,
+        sampler: &mut impl Sampler,
+        ray: &Ray,
+        hit: Option<HitRecord<'w, M>>,
+        world: &'w (impl Hittable<M> + ?Sized),
+    ) -> FilmSample {
+        match ray.lambda {
+            Some(_) => self
+                .trace::<M, SampledSpectrum>(sampler, ray, hit, world)
+                .film_sample(ray),
+            None => self
+                .trace::<M, Vec3>(sampler, ray, hit, world)
+                .film_sample(ray),
+        }
+    }
+}
+
+/// Light reaching the camera after at most one bounce off a rough surface,
+/// mirrors and glass in front of it being seen through.
+#[derive(Debug, Clone)]
+pub struct DirectLighting {
+    max_depth: u32,
+}
+
+impl Default for DirectLighting {
+    fn default() -> Self {
+        Self { max_depth: 10 }
+    }
+}
+
+impl DirectLighting {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Most surfaces hit along a path, specular ones included.
+    pub fn max_depth(mut self, depth: u32) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    fn trace<'w, M: Material, R: PathRadiance>(
+        &self,
+        sampler: &mut impl Sampler,
+        ray: &Ray,
+        hit: Option<HitRecord<'w, M>>,
+        world: &'w (impl Hittable<M> + ?Sized),
+    ) -> PathResult<R> {
+        let mut result = PathResult::new();
+        let mut throughput = R::one();
+        let mut ray = ray.clone();
+        let mut specular_tint = Vec3::new(1.0, 1.0, 1.0);
+        let mut first_hit = Some(hit);
+
+        for bounce in 0..self.max_depth {
+            let hit = first_hit
+                .take()
+                .unwrap_or_else(|| world.hit(&ray, Interval::new(0.001, f64::INFINITY)));
+            let Some(h) = hit else {
+                result.direct =
+                    result.direct + throughput * R::from_rgb(&background(&ray).into(), &ray);
+                break;
+            };
+            if bounce == 0 {
+                result.surface = Some(surface_sample(&h, &ray));
+            } else if let Some(surface) = &mut result.surface {
+                surface.albedo = (specular_tint * *h.material.albedo(&h)).into();
+                surface.normal = h.normal;
+            }
+
+            result.direct = result.direct + throughput * R::from_rgb(&h.material.emitted(&h), &ray);
+            let Some(scatter) = h.material.scatter(sampler, &ray, &h) else {
+                break;
+            };
+            throughput =
+                throughput * R::attenuation(&scatter.attenuation, &ray, &scatter.scattered);
+            if h.material.is_specular() {
+                specular_tint *= *scatter.attenuation;
+                ray = scatter.scattered;
+                continue;
+            }
+
+            // Only what the scattered ray sees directly counts.
+            let scattered = &scatter.scattered;
+            let light = match world.hit(scattered, Interval::new(0.001, f64::INFINITY)) {
+                Some(light) => light.material.emitted(&light),
+                None => background(scattered).into(),
+            };
+            result.direct = result.direct + throughput * R::from_rgb(&light, scattered);
+            break;
+        }
+
+        result
+    }
+}
+
+impl Integrator for DirectLighting {
+    fn sample_hit<'w, M: Material>(
+        &self,
+        sampler: &mut impl Sampler,
+        ray: &Ray,
+        hit: Option<HitRecord<'w, M>>,
+        world: &'w (impl Hittable<M> + ?Sized),
+    ) -> FilmSample {
+        match ray.lambda {
+            Some(_) => self
+                .trace::<M, SampledSpectrum>(sampler, ray, hit, world)
+                .film_sample(ray),
+            None => self
+                .trace::<M, Vec3>(sampler, ray, hit, world)
+                .film_sample(ray),
+        }
+    }
+}
+
+/// Fraction of the hemisphere around the first surface hit that is open
+/// within `radius`, white where nothing is in the way. Materials and lights
+/// are ignored.
+#[derive(Debug, Clone)]
+pub struct AmbientOcclusion {
+    radius: f64,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        Self {
+            radius: f64::INFINITY,
+        }
+    }
+}
+
+impl AmbientOcclusion {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Distance past which occluders no longer darken a surface.
+    pub fn radius(mut self, radius: f64) -> Self {
+        self.radius = radius;
+        self
+    }
+}
+
+impl Integrator for AmbientOcclusion {
+    fn sample_hit<'w, M: Material>(
+        &self,
+        sampler: &mut impl Sampler,
+        ray: &Ray,
+        hit: Option<HitRecord<'w, M>>,
+        world: &'w (impl Hittable<M> + ?Sized),
+    ) -> FilmSample {
+        let Some(h) = hit else {
+            return FilmSample {
+                direct: Vec3::new(1.0, 1.0, 1.0),
+                ..Default::default()
+            };
+        };
+
+        // Cosine weighted, so the fraction of unoccluded rays is the
+        // estimate itself.
+        let direction =
+            Onb::new(&h.normal).transform(&Vec3::sample_cosine_direction(sampler.get_2d()));
+        let occluded = world.occluded(
+            &ray.spawn(h.p, direction),
+            Interval::new(0.001, self.radius),
+        );
+        let open = if occluded { 0.0 } else { 1.0 };
+        debug_sample(&h, ray, Vec3::new(open, open, open))
+    }
+}
+
+/// Shading normal at the first surface hit, mapped from [-1, 1] to [0, 1].
+#[derive(Debug, Clone, Default)]
+pub struct Normals;
+
+impl Integrator for Normals {
+    fn sample_hit<'w, M: Material>(
+        &self,
+        _sampler: &mut impl Sampler,
+        ray: &Ray,
+        hit: Option<HitRecord<'w, M>>,
+        _world: &'w (impl Hittable<M> + ?Sized),
+    ) -> FilmSample {
+        let Some(h) = hit else {
+            return FilmSample::default();
+        };
+        debug_sample(&h, ray, (h.normal + Vec3::new(1.0, 1.0, 1.0)) * 0.5)
+    }
+}
+
+/// Surface coordinates of the first surface hit, u in red and v in green.
+#[derive(Debug, Clone, Default)]
+pub struct Uv;
+
+impl Integrator for Uv {
+    fn sample_hit<'w, M: Material>(
+        &self,
+        _sampler: &mut impl Sampler,
+        ray: &Ray,
+        hit: Option<HitRecord<'w, M>>,
+        _world: &'w (impl Hittable<M> + ?Sized),
+    ) -> FilmSample {
+        let Some(h) = hit else {
+            return FilmSample::default();
+        };
+        debug_sample(&h, ray, Vec3::new(h.u, h.v, 0.0))
+    }
+}
+
+/// Distance to the first surface hit, from black at the camera to white at
+/// `far` and beyond.
+#[derive(Debug, Clone)]
+pub struct Depth {
+    far: f64,
+}
+
+impl Default for Depth {
+    fn default() -> Self {
+        Self { far: 20.0 }
+    }
+}
+
+impl Depth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn far(mut self, far: f64) -> Self {
+        self.far = far;
+        self
+    }
+}
+
+impl Integrator for Depth {
+    fn sample_hit<'w, M: Material>(
+        &self,
+        _sampler: &mut impl Sampler,
+        ray: &Ray,
+        hit: Option<HitRecord<'w, M>>,
+        _world: &'w (impl Hittable<M> + ?Sized),
+    ) -> FilmSample {
+        let Some(h) = hit else {
+            return FilmSample {
+                direct: Vec3::new(1.0, 1.0, 1.0),
+                ..Default::default()
+            };
+        };
+        let depth = (h.t * ray.dir.length() / self.far).min(1.0);
+        debug_sample(&h, ray, Vec3::new(depth, depth, depth))
+    }
+}
+
+/// Bounding box and primitive tests taken to find the first surface hit, from
+/// blue for none through green to red at `max_steps` and beyond.
+#[derive(Debug, Clone)]
+pub struct TraversalSteps {
+    max_steps: u32,
+}
+
+impl Default for TraversalSteps {
+    fn default() -> Self {
+        Self { max_steps: 100 }
+    }
+}
+
+impl TraversalSteps {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn max_steps(mut self, steps: u32) -> Self {
+        self.max_steps = steps.max(1);
+        self
+    }
+}
+
+impl Integrator for TraversalSteps {
+    fn sample_hit<'w, M: Material>(
+        &self,
+        _sampler: &mut impl Sampler,
+        ray: &Ray,
+        hit: Option<HitRecord<'w, M>>,
+        world: &'w (impl Hittable<M> + ?Sized),
+    ) -> FilmSample {
+        let steps = world.traversal_steps(ray, Interval::new(0.001, f64::INFINITY));
+        let color = *heatmap_color((steps as f64 / self.max_steps as f64).min(1.0));
+        match hit {
+            Some(h) => debug_sample(&h, ray, color),
+            None => FilmSample {
+                direct: color,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Random looking color for the index of each object hit, black where
+/// nothing is.
+#[derive(Debug, Clone, Default)]
+pub struct ObjectId;
+
+impl Integrator for ObjectId {
+    fn sample_hit<'w, M: Material>(
+        &self,
+        _sampler: &mut impl Sampler,
+        ray: &Ray,
+        hit: Option<HitRecord<'w, M>>,
+        _world: &'w (impl Hittable<M> + ?Sized),
+    ) -> FilmSample {
+        let Some(h) = hit else {
+            return FilmSample::default();
+        };
+        // Bits of a hash of the ID, so that neighbouring IDs stand apart.
+        let hash = (h.object_id as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        let channel = |shift: u32| 0.2 + 0.8 * ((hash >> shift) & 0xff) as f64 / 255.0;
+        debug_sample(&h, ray, Vec3::new(channel(40), channel(48), channel(56)))
+    }
+}
@@ -1,10 +1,32 @@
+pub mod aabb;
+pub mod animation;
+pub mod aperture;
+pub mod bdpt;
+pub mod bvh_node;
 pub mod camera;
 pub mod color;
+pub mod denoise;
+pub mod exr;
+pub mod film;
+pub mod filter;
 pub mod hittable;
+pub mod integrator;
 pub mod interval;
+pub mod light;
 pub mod material;
+pub mod metropolis;
+pub mod microfacet;
+pub mod onb;
+pub mod packet;
+pub mod photon;
+pub mod progressive;
+pub mod quad;
 pub mod ray;
+pub mod sampler;
+pub mod shutter;
+pub mod spectrum;
 pub mod sphere;
+pub mod texture;
+pub mod transform;
 pub mod vec3;
-pub mod aabb;
-pub mod bvh_node;
+pub mod wide_bvh;
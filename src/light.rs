@@ -0,0 +1,112 @@
+use std::f64::consts::PI;
+
+use crate::{
+    color::Color,
+    vec3::{Point, Vec3},
+};
+
+/// Shape of an area light, matching the object that emits the light.
+#[derive(Debug, Clone)]
+pub enum LightShape {
+    Sphere {
+        center: Point,
+        radius: f64,
+    },
+    /// Parallelogram spanned by `u` and `v` from the corner `q`, as `Quad`.
+    Quad {
+        q: Point,
+        u: Vec3,
+        v: Vec3,
+    },
+}
+
+/// Emitting object of the scene, which integrators can start paths from.
+/// Both sides of the surface emit `radiance`, as emissive materials do.
+#[derive(Debug, Clone)]
+pub struct Light {
+    // Object ID reported by hits on the emitting object
+    object_id: u32,
+    shape: LightShape,
+    radiance: Color,
+}
+
+impl Light {
+    /// Light for the object of index `object_id` in the scene list, which
+    /// has the given shape and emits `radiance`.
+    pub fn new(object_id: u32, shape: LightShape, radiance: Color) -> Self {
+        Self {
+            object_id,
+            shape,
+            radiance,
+        }
+    }
+
+    pub fn object_id(&self) -> u32 {
+        self.object_id
+    }
+
+    pub fn radiance(&self) -> Color {
+        self.radiance
+    }
+
+    pub fn area(&self) -> f64 {
+        match &self.shape {
+            LightShape::Sphere { radius, .. } => 4.0 * PI * radius * radius,
+            LightShape::Quad { u, v, .. } => u.cross(v).length(),
+        }
+    }
+
+    /// Uniformly distributed point of the surface from a uniform point of
+    /// [0,1)^2, with the unit normal there.
+    pub fn sample_point(&self, u: (f64, f64)) -> (Point, Vec3) {
+        match &self.shape {
+            LightShape::Sphere { center, radius } => {
+                let normal = Vec3::sample_unit(u);
+                (*center + normal * *radius, normal)
+            }
+            LightShape::Quad {
+                q,
+                u: edge_u,
+                v: edge_v,
+            } => {
+                let p = *q + *edge_u * u.0 + *edge_v * u.1;
+                (p, edge_u.cross(edge_v).unit_vector())
+            }
+        }
+    }
+}
+
+/// Lights of a scene, picked with equal probability.
+#[derive(Debug, Clone, Default)]
+pub struct LightList {
+    lights: Vec<Light>,
+}
+
+impl LightList {
+    pub fn new(lights: Vec<Light>) -> Self {
+        Self { lights }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    /// Probability of picking any one light.
+    pub fn pick_pdf(&self) -> f64 {
+        1.0 / self.lights.len() as f64
+    }
+
+    /// Light picked by `u` in [0,1), none for an empty list.
+    pub fn pick(&self, u: f64) -> Option<&Light> {
+        let index = (u * self.lights.len() as f64) as usize;
+        self.lights
+            .get(index.min(self.lights.len().saturating_sub(1)))
+    }
+
+    /// Light of the object with the given ID, if it is in the list.
+    pub fn by_object(&self, object_id: u32) -> Option<&Light> {
+        self.lights
+            .iter()
+            .find(|light| light.object_id == object_id)
+    }
+}
@@ -1,15 +1,269 @@
-use rand::SeedableRng;
+use std::{env, fs::File, io::BufWriter, process};
+
+use rand::{Rng, SeedableRng};
 use rand_chacha::ChaCha12Rng;
 use ray_tracing_in_one_weekend::{
-    camera::CameraBuilder,
+    animation::{Interpolation, Track},
+    bdpt::Bidirectional,
+    camera::{Camera, CameraBuilder},
     color::Color,
+    denoise::Denoiser,
+    film::Film,
+    filter::Filter,
+    hittable::Hittable,
+    integrator::{
+        AmbientOcclusion, Depth, DirectLighting, IntegratorKind, Normals, ObjectId, PathTracer,
+        TraversalSteps, Uv,
+    },
+    light::LightList,
     material::{Dielectric, Lambertian, MaterialKind, Metal},
+    metropolis::Metropolis,
+    progressive::sample_heatmap,
+    sampler::SamplePattern,
     sphere::Sphere,
     vec3::{Point, Vec3},
+    wide_bvh::WideBVH,
 };
-use rayon::prelude::*;
+
+// Most bounces a path takes, Russian roulette ends most of them far earlier
+const MAX_DEPTH: u32 = 50;
+
+struct Args {
+    // Inclusive range of frames to render to files, a single image to stdout otherwise
+    frames: Option<(u32, u32)>,
+    output: String,
+    // Every random number of the run derives from it
+    seed: u64,
+    samples: u32,
+    // Relative error at which adaptive sampling stops a pixel
+    adaptive: Option<f64>,
+    // Where to write the per-pixel sample counts, a pattern like `output` for
+    // sequences
+    heatmap: Option<String>,
+    // Where to write every pass as a multi-layer EXR, a pattern for sequences
+    exr: Option<String>,
+    // Write the denoised beauty pass instead of the raw one
+    denoise: bool,
+    sampler: SamplePattern,
+    filter: Filter,
+    integrator: IntegratorKind,
+    // Render with Metropolis light transport, `samples` being mutations per
+    // pixel
+    metropolis: bool,
+}
+
+impl Args {
+    fn parse() -> Result<Self, String> {
+        let mut args = Args {
+            frames: None,
+            output: "frame_####.ppm".to_string(),
+            seed: 0,
+            samples: 100,
+            adaptive: None,
+            heatmap: None,
+            exr: None,
+            denoise: false,
+            sampler: SamplePattern::Independent,
+            filter: Filter::default(),
+            integrator: IntegratorKind::Path(PathTracer::new().max_depth(MAX_DEPTH)),
+            metropolis: false,
+        };
+
+        // Applies to `--integrator ao` wherever it comes on the command line
+        let mut ao_radius = None;
+        let mut given = Vec::new();
+
+        let mut iter = env::args().skip(1);
+        while let Some(arg) = iter.next() {
+            given.push(arg.clone());
+            let mut value = || iter.next().ok_or(format!("missing value for {arg}"));
+            match arg.as_str() {
+                "--frames" => {
+                    let value = value()?;
+                    let (first, last) = value
+                        .split_once("..")
+                        .and_then(|(a, b)| Some((a.parse().ok()?, b.parse().ok()?)))
+                        .ok_or(format!("invalid frame range {value}, expected N..M"))?;
+                    if last < first {
+                        return Err(format!("frame range {value} ends before it starts"));
+                    }
+                    args.frames = Some((first, last));
+                }
+                "--output" => args.output = value()?,
+                "--seed" => {
+                    let value = value()?;
+                    args.seed = value
+                        .parse()
+                        .map_err(|_| format!("invalid seed {value}, expected an integer"))?;
+                }
+                "--samples" => {
+                    let value = value()?;
+                    args.samples = value
+                        .parse()
+                        .map_err(|_| format!("invalid sample count {value}"))?;
+                }
+                "--adaptive" => {
+                    let value = value()?;
+                    let error = value
+                        .parse()
+                        .map_err(|_| format!("invalid relative error {value}"))?;
+                    args.adaptive = Some(error);
+                }
+                "--heatmap" => args.heatmap = Some(value()?),
+                "--exr" => args.exr = Some(value()?),
+                "--denoise" => args.denoise = true,
+                "--metropolis" => args.metropolis = true,
+                "--sampler" => {
+                    args.sampler = match value()?.as_str() {
+                        "independent" => SamplePattern::Independent,
+                        "stratified" => SamplePattern::Stratified,
+                        "halton" => SamplePattern::Halton,
+                        "sobol" => SamplePattern::Sobol,
+                        "blue-noise" => SamplePattern::BlueNoise,
+                        other => return Err(format!("unknown sampler {other}")),
+                    }
+                }
+                "--filter" => {
+                    args.filter = match value()?.as_str() {
+                        "box" => Filter::Box { radius: 0.5 },
+                        "tent" => Filter::Tent { radius: 1.0 },
+                        "gaussian" => Filter::Gaussian {
+                            radius: 1.5,
+                            sigma: 0.5,
+                        },
+                        "mitchell" => Filter::Mitchell {
+                            radius: 2.0,
+                            b: 1.0 / 3.0,
+                            c: 1.0 / 3.0,
+                        },
+                        "lanczos" => Filter::Lanczos { radius: 3.0 },
+                        other => return Err(format!("unknown filter {other}")),
+                    }
+                }
+                "--integrator" => {
+                    args.integrator = match value()?.as_str() {
+                        "path" => IntegratorKind::Path(PathTracer::new().max_depth(MAX_DEPTH)),
+                        "direct" => {
+                            IntegratorKind::Direct(DirectLighting::new().max_depth(MAX_DEPTH))
+                        }
+                        "ao" => IntegratorKind::AmbientOcclusion(AmbientOcclusion::new()),
+                        "normals" => IntegratorKind::Normals(Normals),
+                        "bdpt" => IntegratorKind::Bidirectional(Bidirectional::new()),
+                        "uv" => IntegratorKind::Uv(Uv),
+                        "depth" => IntegratorKind::Depth(Depth::new()),
+                        "bvh" => IntegratorKind::TraversalSteps(TraversalSteps::new()),
+                        "ids" => IntegratorKind::ObjectId(ObjectId),
+                        other => return Err(format!("unknown integrator {other}")),
+                    }
+                }
+                "--ao-radius" => {
+                    let value = value()?;
+                    let radius = value
+                        .parse()
+                        .map_err(|_| format!("invalid occlusion radius {value}"))?;
+                    ao_radius = Some(radius);
+                }
+                _ => return Err(format!("unknown argument {arg}")),
+            }
+        }
+        if let (IntegratorKind::AmbientOcclusion(_), Some(radius)) = (&args.integrator, ao_radius) {
+            args.integrator =
+                IntegratorKind::AmbientOcclusion(AmbientOcclusion::new().radius(radius));
+        }
+        // Metropolis splats whole mutations into single pixels and records no
+        // sample counts or surface passes.
+        if args.metropolis {
+            let unsupported = [
+                "--filter",
+                "--adaptive",
+                "--sampler",
+                "--heatmap",
+                "--exr",
+                "--denoise",
+            ];
+            if let Some(flag) = given.iter().find(|arg| unsupported.contains(&arg.as_str())) {
+                return Err(format!("{flag} can't be combined with --metropolis"));
+            }
+        }
+        Ok(args)
+    }
+}
+
+fn write_image(path: &str, pixels: &[Color], width: u32, height: u32) {
+    let written = File::create(path).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        Color::write_pixels(&mut out, pixels, width, height)
+    });
+    if let Err(err) = written {
+        eprintln!("failed to write {path}: {err}");
+        process::exit(1);
+    }
+    eprintln!("Wrote {path}");
+}
+
+/// Renders the film of `cam`, with Metropolis light transport if given.
+fn render(
+    cam: &Camera,
+    seed: u64,
+    world: &(impl Hittable<MaterialKind> + Sync),
+    metropolis: Option<&Metropolis>,
+) -> Film {
+    match metropolis {
+        Some(metropolis) => metropolis.render(seed, cam, world),
+        None => cam.render(seed, world),
+    }
+}
+
+/// Beauty pass of the film, denoised if asked for.
+fn final_image(film: &Film, denoise: bool) -> Vec<Color> {
+    if denoise {
+        Denoiser::new().denoise(film)
+    } else {
+        film.image()
+    }
+}
+
+fn write_exr(path: &str, film: &Film) {
+    let written = File::create(path).and_then(|file| film.write_exr(&mut BufWriter::new(file)));
+    if let Err(err) = written {
+        eprintln!("failed to write {path}: {err}");
+        process::exit(1);
+    }
+    eprintln!("Wrote {path}");
+}
+
+/// Replaces the run of `#` in `pattern` with the zero padded frame number.
+fn frame_path(pattern: &str, frame: u32) -> String {
+    match pattern.find('#') {
+        Some(start) => {
+            let width = pattern[start..].chars().take_while(|&c| c == '#').count();
+            format!(
+                "{}{:0width$}{}",
+                &pattern[..start],
+                frame,
+                &pattern[start + width..]
+            )
+        }
+        None => format!("{pattern}{frame:04}"),
+    }
+}
 
 fn main() {
+    let args = Args::parse().unwrap_or_else(|err| {
+        eprintln!("{err}");
+        eprintln!("usage: raytracer [--frames N..M] [--output frame_####.ppm] [--seed N]");
+        eprintln!("                 [--samples N]");
+        eprintln!("                 [--adaptive ERROR] [--heatmap heatmap_####.ppm]");
+        eprintln!("                 [--exr passes_####.exr] [--denoise]");
+        eprintln!("                 [--sampler independent|stratified|halton|sobol|blue-noise]");
+        eprintln!("                 [--filter box|tent|gaussian|mitchell|lanczos]");
+        eprintln!("                 [--integrator path|direct|ao|normals|bdpt|uv|depth|bvh|ids]");
+        eprintln!("                 [--ao-radius R] [--metropolis]");
+        process::exit(2);
+    });
+
+    let mut rng = ChaCha12Rng::seed_from_u64(args.seed);
+
     // World
     let mut materials: Vec<MaterialKind> = Vec::new();
 
@@ -17,23 +271,23 @@ fn main() {
 
     for a in -11..11 {
         for b in -11..11 {
-            let chose_mat: f64 = rand::random();
+            let chose_mat: f64 = rng.random();
             let center = Point::new(
-                a as f64 + 0.9 * rand::random::<f64>(),
+                a as f64 + 0.9 * rng.random::<f64>(),
                 0.2,
-                b as f64 + 0.9 * rand::random::<f64>(),
+                b as f64 + 0.9 * rng.random::<f64>(),
             );
 
             if (center - Point::new(4.0, 0.2, 0.0)).length() > 0.9 {
                 world_centers.push(center);
                 if chose_mat < 0.8 {
                     // diffuse
-                    let albedo = Vec3::random(&mut rand::rng()) * Vec3::random(&mut rand::rng());
+                    let albedo = Vec3::random(&mut rng) * Vec3::random(&mut rng);
                     materials.push(MaterialKind::Lambertian(Lambertian::new(albedo.into())));
                 } else if chose_mat < 0.95 {
                     // metal
-                    let albedo = Vec3::random_range(&mut rand::rng(), 0.5, 1.0);
-                    let fuz: f64 = rand::random_range(0.0..0.5);
+                    let albedo = Vec3::random_range(&mut rng, 0.5, 1.0);
+                    let fuz: f64 = rng.random_range(0.0..0.5);
                     materials.push(MaterialKind::Metal(Metal::new(albedo.into(), fuz)));
                 } else {
                     // glass
@@ -66,51 +320,125 @@ fn main() {
             .get(idx)
             .expect("materials and centers are not the same size");
         match mat {
-            MaterialKind::Lambertian(_) => {
-                let center2 = *center + Vec3::new(0.0, rand::random_range(0.0..0.5), 0.0);
+            // Bouncing spheres blur within a single image, an animation only
+            // moves the camera.
+            MaterialKind::Lambertian(_) if args.frames.is_none() => {
+                let center2 = *center + Vec3::new(0.0, rng.random_range(0.0..0.5), 0.0);
                 world.push(Sphere::new_moving(*center, center2, 0.2, mat));
             }
             _ => world.push(Sphere::new(*center, 0.2, mat)),
         };
     }
 
+    // Light paths start from the emitting spheres, found by their index in
+    // the list as the BVH numbers objects.
+    let integrator = match args.integrator {
+        IntegratorKind::Bidirectional(bdpt) => {
+            let lights = world
+                .iter()
+                .enumerate()
+                .filter_map(|(id, sphere)| sphere.light(id as u32))
+                .collect();
+            let lights = LightList::new(lights);
+            if lights.is_empty() {
+                eprintln!("bdpt needs a scene with emitters, and this one has none");
+                process::exit(2);
+            }
+            IntegratorKind::Bidirectional(bdpt.lights(lights))
+        }
+        integrator => integrator,
+    };
+
+    let world = WideBVH::new_from_hittable_list(world);
+    let render_seed: u64 = rng.random();
+
     // Camera
     let aspect_ratio: f64 = 16.0 / 9.0;
     let image_width: u32 = 400;
 
-    let cam = CameraBuilder::new()
+    let mut camera = CameraBuilder::new()
         .image_width(image_width)
         .aspect_ratio(aspect_ratio)
-        .samples_per_pixel(10)
-        .max_depth(50)
+        .samples_per_pixel(args.samples)
         .vfov(20.0)
         .lookfrom(Point::new(13.0, 2.0, 3.0))
         .lookat(Point::new(0.0, 0.0, 0.0))
         .vup(Vec3::new(0.0, 1.0, 0.0))
         .defocus_angle(0.6)
         .focus_dist(10.0)
-        .build();
-
-    //render
-    let n_passes = 10;
-    let results: Vec<Vec<Color>> = (0..n_passes)
-        .into_par_iter()
-        .map(|_| {
-            let mut rng = ChaCha12Rng::from_rng(&mut rand::rng());
-            cam.render(&mut rng, &world)
-        })
-        .collect();
-
-    // Average all runs
-    let mut final_image = vec![Color::default(); (cam.image_width * cam.image_height) as usize];
-    for image in results {
-        for (i, pixel) in image.into_iter().enumerate() {
-            final_image[i] += pixel;
+        .sampler(args.sampler)
+        .filter(args.filter)
+        .integrator(integrator);
+    if let Some(error) = args.adaptive {
+        camera = camera.adaptive_error(error);
+    }
+
+    let metropolis = args
+        .metropolis
+        .then(|| Metropolis::new().mutations_per_pixel(args.samples));
+
+    let Some((first, last)) = args.frames else {
+        let cam = camera.build();
+        let film = render(&cam, render_seed, &world, metropolis.as_ref());
+        if let Some(path) = &args.heatmap {
+            let heatmap = sample_heatmap(&film.sample_counts());
+            write_image(path, &heatmap, film.width(), film.height());
         }
+        if let Some(path) = &args.exr {
+            write_exr(path, &film);
+        }
+        let image = final_image(&film, args.denoise);
+        Color::output_pixels(image, film.width(), film.height());
+        return;
+    };
+
+    // Orbit around the scene over the sequence, time being counted in frames.
+    // Counted in floats, the range may hold every u32.
+    let frame_count = (last - first) as f64 + 1.0;
+    let orbit = |frame: f64| {
+        let angle = 2.0 * std::f64::consts::PI * (frame - first as f64) / frame_count;
+        let (sin, cos) = angle.sin_cos();
+        Point::new(13.0 * cos - 3.0 * sin, 2.0, 13.0 * sin + 3.0 * cos)
+    };
+    let mut lookfrom = Track::new();
+    for key in 0..=8 {
+        let frame = first as f64 + frame_count * key as f64 / 8.0;
+        lookfrom.insert_with(frame, orbit(frame), Interpolation::CatmullRom);
     }
 
-    for pixel in &mut final_image {
-        *pixel /= n_passes as f64;
+    // The world is static, so its BVH is shared by every frame.
+    for frame in first..=last {
+        let time = frame as f64;
+        let cam = camera
+            .clone()
+            .lookfrom_keyframe(time, lookfrom.value_at(time))
+            .lookfrom_keyframe(time + 1.0, lookfrom.value_at(time + 1.0))
+            .shutter(time, time + 0.5)
+            .build();
+        let film = render(
+            &cam,
+            render_seed.wrapping_add(frame as u64),
+            &world,
+            metropolis.as_ref(),
+        );
+
+        write_image(
+            &frame_path(&args.output, frame),
+            &final_image(&film, args.denoise),
+            film.width(),
+            film.height(),
+        );
+        if let Some(pattern) = &args.heatmap {
+            let heatmap = sample_heatmap(&film.sample_counts());
+            write_image(
+                &frame_path(pattern, frame),
+                &heatmap,
+                film.width(),
+                film.height(),
+            );
+        }
+        if let Some(pattern) = &args.exr {
+            write_exr(&frame_path(pattern, frame), &film);
+        }
     }
-    Color::output_pixels(final_image, cam.image_width, cam.image_height);
 }
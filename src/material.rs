@@ -1,6 +1,16 @@
-use rand::Rng;
+use std::f64::consts::PI;
 
-use crate::{color::Color, hittable::HitRecord, ray::Ray, vec3::Vec3};
+use crate::{
+    animation::Track,
+    color::Color,
+    hittable::HitRecord,
+    microfacet::{self, TrowbridgeReitz},
+    onb::Onb,
+    ray::Ray,
+    sampler::Sampler,
+    texture::{ScalarTexture, Texture, TextureKind},
+    vec3::Vec3,
+};
 
 #[derive(Clone, Debug)]
 pub struct Scatter {
@@ -11,10 +21,63 @@ pub struct Scatter {
 pub trait Material {
     fn scatter<T>(
         &self,
-        rng: &mut impl Rng,
+        sampler: &mut impl Sampler,
         ray_in: &Ray,
         hit_record: &HitRecord<T>,
     ) -> Option<Scatter>;
+
+    fn emitted<T>(&self, _hit_record: &HitRecord<T>) -> Color {
+        Color::default()
+    }
+
+    /// Radiance emitted by every point of the surface, for materials whose
+    /// emission doesn't vary across it, which lights can be made from.
+    fn uniform_emission(&self) -> Option<Color> {
+        None
+    }
+
+    /// Overall surface color at the hit, written to the albedo pass.
+    fn albedo<T>(&self, _hit_record: &HitRecord<T>) -> Color {
+        Color::new(1.0, 1.0, 1.0)
+    }
+
+    /// Whether the surface only reflects or refracts in a single direction,
+    /// the albedo and normal passes then showing what is seen through it.
+    fn is_specular(&self) -> bool {
+        false
+    }
+
+    /// Index of refraction behind the surface over that of the side it was
+    /// hit from, 1 for surfaces light doesn't go through.
+    fn relative_ior<T>(&self, _hit_record: &HitRecord<T>) -> f64 {
+        1.0
+    }
+
+    /// Factor the power of light leaving along `wi` is scaled by. Radiance
+    /// keeps its value across refractions, so light crossing the surface has
+    /// its power scaled by the squared index ratio.
+    fn transmitted_power_scale<T>(&self, hit_record: &HitRecord<T>, wi: &Vec3) -> f64 {
+        if wi.dot(&hit_record.normal) < 0.0 {
+            self.relative_ior(hit_record).powi(-2)
+        } else {
+            1.0
+        }
+    }
+
+    /// BSDF for light arriving along `wi` and leaving along `wo`, both unit
+    /// vectors pointing away from the surface, with the density over solid
+    /// angle of `scatter` picking `wi` when leaving along `wo`. The
+    /// attenuation of a scatter is the BSDF times the cosine over that
+    /// density. `None` for surfaces whose scattering can't be evaluated, such
+    /// as mirrors and glass.
+    fn evaluate<T>(
+        &self,
+        _hit_record: &HitRecord<T>,
+        _wo: &Vec3,
+        _wi: &Vec3,
+    ) -> Option<(Color, f64)> {
+        None
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -22,15 +85,22 @@ pub enum MaterialKind {
     Lambertian(Lambertian),
     Metal(Metal),
     Dielectric(Dielectric),
+    Principled(Box<Principled>),
 }
 
 #[derive(Debug, Clone)]
 pub struct Lambertian {
-    albedo: Color,
+    albedo: Track<Color>,
 }
 
 impl Lambertian {
     pub fn new(albedo: Color) -> Self {
+        Self::new_animated(Track::constant(albedo))
+    }
+
+    /// Lambertian whose albedo changes over time, evaluated at the time of
+    /// each ray.
+    pub fn new_animated(albedo: Track<Color>) -> Self {
         Self { albedo }
     }
 }
@@ -38,22 +108,36 @@ impl Lambertian {
 impl Material for Lambertian {
     fn scatter<T>(
         &self,
-        rng: &mut impl Rng,
+        sampler: &mut impl Sampler,
         r_in: &Ray,
         hit_record: &HitRecord<T>,
     ) -> Option<Scatter> {
-        let mut scatter_dir = hit_record.normal + Vec3::random_unit(rng);
+        let mut scatter_dir = hit_record.normal + Vec3::sample_unit(sampler.get_2d());
 
         if scatter_dir.near_zero() {
             scatter_dir = hit_record.normal
         }
 
-        let scattered = Ray::at_time(hit_record.p, scatter_dir, r_in.time);
+        let scattered = r_in.spawn(hit_record.p, scatter_dir);
         Some(Scatter {
-            attenuation: self.albedo,
+            attenuation: self.albedo.value_at(hit_record.time),
             scattered,
         })
     }
+
+    fn albedo<T>(&self, hit_record: &HitRecord<T>) -> Color {
+        self.albedo.value_at(hit_record.time)
+    }
+
+    fn evaluate<T>(&self, hit_record: &HitRecord<T>, wo: &Vec3, wi: &Vec3) -> Option<(Color, f64)> {
+        // Only reflects on the side the surface was hit from.
+        let cos_theta = wi.dot(&hit_record.normal);
+        if cos_theta <= 0.0 || wo.dot(&hit_record.normal) <= 0.0 {
+            return Some((Color::default(), 0.0));
+        }
+        let albedo = self.albedo.value_at(hit_record.time);
+        Some(((*albedo / PI).into(), cos_theta / PI))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -74,14 +158,15 @@ impl Metal {
 impl Material for Metal {
     fn scatter<T>(
         &self,
-        rng: &mut impl Rng,
+        sampler: &mut impl Sampler,
         ray_in: &Ray,
         hit_record: &HitRecord<T>,
     ) -> Option<Scatter> {
-        // Small displace on sphere fuzz
-        let reflected =
-            ray_in.dir.reflect(&hit_record.normal) + (Vec3::random_unit(rng) * self.fuzz);
-        let scattered = Ray::at_time(hit_record.p, reflected, ray_in.time);
+        // Small displace on sphere fuzz, around the unit mirror direction so
+        // that it doesn't depend on the length of the incoming ray.
+        let reflected = ray_in.dir.unit_vector().reflect(&hit_record.normal)
+            + (Vec3::sample_unit(sampler.get_2d()) * self.fuzz);
+        let scattered = ray_in.spawn(hit_record.p, reflected);
 
         // check if ray reflect is wrong dir after fuzz
         if scattered.dir.dot(&hit_record.normal) > 0.0 {
@@ -93,16 +178,91 @@ impl Material for Metal {
             None
         }
     }
+
+    fn albedo<T>(&self, _hit_record: &HitRecord<T>) -> Color {
+        self.albedo
+    }
+
+    fn is_specular(&self) -> bool {
+        self.fuzz == 0.0
+    }
+
+    fn evaluate<T>(&self, hit_record: &HitRecord<T>, wo: &Vec3, wi: &Vec3) -> Option<(Color, f64)> {
+        if self.is_specular() {
+            return None;
+        }
+        let cos_theta = wi.dot(&hit_record.normal);
+        if cos_theta <= 0.0 || wo.dot(&hit_record.normal) <= 0.0 {
+            return Some((Color::default(), 0.0));
+        }
+        // Scattered directions go through a point spread uniformly over the
+        // sphere of radius fuzz around the mirror direction. Both points of
+        // the sphere along `wi` lead there.
+        let b = wi.dot(&(-*wo).reflect(&hit_record.normal));
+        let discriminant = b * b - 1.0 + self.fuzz * self.fuzz;
+        if b <= 0.0 || discriminant <= 0.0 {
+            return Some((Color::default(), 0.0));
+        }
+        let pdf = (b * b + discriminant) / (2.0 * PI * self.fuzz * discriminant.sqrt());
+        Some(((*self.albedo * (pdf / cos_theta)).into(), pdf))
+    }
+}
+
+/// Wavelength dependent index of refraction, with wavelengths in micrometres.
+#[derive(Debug, Clone)]
+pub enum Dispersion {
+    /// n(λ) = a + b / λ²
+    Cauchy { a: f64, b: f64 },
+    /// n²(λ) = 1 + Σ bᵢ λ² / (λ² - cᵢ)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    pub const BK7: Dispersion = Dispersion::Sellmeier {
+        b: [1.039_612_12, 0.231_792_344, 1.010_469_45],
+        c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653],
+    };
+
+    pub const DIAMOND: Dispersion = Dispersion::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030_625, 0.011_236, 0.0],
+    };
+
+    pub fn ior(&self, lambda_nm: f64) -> f64 {
+        let l = lambda_nm * 1e-3;
+        let l2 = l * l;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let n2 = 1.0 + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>();
+                n2.sqrt()
+            }
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Dielectric {
     refraction_index: f64,
+    dispersion: Option<Dispersion>,
 }
 
 impl Dielectric {
+    // Wavelength used for the index of refraction when rendering in RGB
+    const REFERENCE_WAVELENGTH: f64 = 587.6;
+
     pub fn new(refraction_index: f64) -> Self {
-        Self { refraction_index }
+        Self {
+            refraction_index,
+            dispersion: None,
+        }
+    }
+
+    pub fn new_dispersive(dispersion: Dispersion) -> Self {
+        Self {
+            refraction_index: dispersion.ior(Self::REFERENCE_WAVELENGTH),
+            dispersion: Some(dispersion),
+        }
     }
 }
 
@@ -119,14 +279,25 @@ impl Dielectric {
 impl Material for Dielectric {
     fn scatter<T>(
         &self,
-        _: &mut impl Rng,
+        sampler: &mut impl Sampler,
         ray_in: &Ray,
         hit_record: &HitRecord<T>,
     ) -> Option<Scatter> {
+        // Dispersive glass bends each wavelength differently, so only the hero
+        // wavelength can follow the refracted path.
+        let mut lambda = ray_in.lambda;
+        let refraction_index = match (&self.dispersion, &mut lambda) {
+            (Some(dispersion), Some(lambda)) => {
+                lambda.terminate_secondary();
+                dispersion.ior(lambda.hero())
+            }
+            _ => self.refraction_index,
+        };
+
         let ri = if hit_record.front_face {
-            1.0 / self.refraction_index
+            1.0 / refraction_index
         } else {
-            self.refraction_index
+            refraction_index
         };
         let unit_dir = ray_in.dir.unit_vector();
         let cos_theta = (-unit_dir).dot(&hit_record.normal).min(1.0);
@@ -134,7 +305,7 @@ impl Material for Dielectric {
 
         let cannot_refract = ri * sin_theta > 1.0;
 
-        let direction = if cannot_refract || (Self::reflectance(cos_theta, ri) > rand::random()) {
+        let direction = if cannot_refract || (Self::reflectance(cos_theta, ri) > sampler.get_1d()) {
             unit_dir.reflect(&hit_record.normal)
         } else {
             unit_dir.refract(&hit_record.normal, ri)
@@ -142,22 +313,481 @@ impl Material for Dielectric {
 
         Some(Scatter {
             attenuation: Color::new(1.0, 1.0, 1.0),
-            scattered: Ray::at_time(hit_record.p, direction, ray_in.time),
+            scattered: Ray {
+                lambda,
+                ..ray_in.spawn(hit_record.p, direction)
+            },
         })
     }
+
+    fn is_specular(&self) -> bool {
+        true
+    }
+
+    fn relative_ior<T>(&self, hit_record: &HitRecord<T>) -> f64 {
+        if hit_record.front_face {
+            self.refraction_index
+        } else {
+            1.0 / self.refraction_index
+        }
+    }
+}
+
+/// Layered material following the Disney / glTF principled parameters. Lobes
+/// are picked stochastically, each sample following a single one of them.
+#[derive(Debug, Clone)]
+pub struct Principled {
+    base_color: TextureKind,
+    metallic: ScalarTexture,
+    roughness: ScalarTexture,
+    specular: ScalarTexture,
+    specular_tint: ScalarTexture,
+    sheen: ScalarTexture,
+    sheen_tint: ScalarTexture,
+    clearcoat: ScalarTexture,
+    clearcoat_roughness: ScalarTexture,
+    transmission: ScalarTexture,
+    ior: f64,
+    emission: TextureKind,
+    emission_strength: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct PrincipledBuilder {
+    material: Principled,
+}
+
+impl Default for PrincipledBuilder {
+    fn default() -> Self {
+        Self {
+            material: Principled {
+                base_color: Color::new(0.8, 0.8, 0.8).into(),
+                metallic: 0.0.into(),
+                roughness: 0.5.into(),
+                specular: 0.5.into(),
+                specular_tint: 0.0.into(),
+                sheen: 0.0.into(),
+                sheen_tint: 0.5.into(),
+                clearcoat: 0.0.into(),
+                clearcoat_roughness: 0.03.into(),
+                transmission: 0.0.into(),
+                ior: 1.5,
+                emission: Color::default().into(),
+                emission_strength: 1.0,
+            },
+        }
+    }
+}
+
+impl PrincipledBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn base_color(mut self, tex: impl Into<TextureKind>) -> Self {
+        self.material.base_color = tex.into();
+        self
+    }
+
+    pub fn metallic(mut self, tex: impl Into<ScalarTexture>) -> Self {
+        self.material.metallic = tex.into();
+        self
+    }
+
+    pub fn roughness(mut self, tex: impl Into<ScalarTexture>) -> Self {
+        self.material.roughness = tex.into();
+        self
+    }
+
+    pub fn specular(mut self, tex: impl Into<ScalarTexture>) -> Self {
+        self.material.specular = tex.into();
+        self
+    }
+
+    pub fn specular_tint(mut self, tex: impl Into<ScalarTexture>) -> Self {
+        self.material.specular_tint = tex.into();
+        self
+    }
+
+    pub fn sheen(mut self, tex: impl Into<ScalarTexture>) -> Self {
+        self.material.sheen = tex.into();
+        self
+    }
+
+    pub fn sheen_tint(mut self, tex: impl Into<ScalarTexture>) -> Self {
+        self.material.sheen_tint = tex.into();
+        self
+    }
+
+    pub fn clearcoat(mut self, tex: impl Into<ScalarTexture>) -> Self {
+        self.material.clearcoat = tex.into();
+        self
+    }
+
+    pub fn clearcoat_roughness(mut self, tex: impl Into<ScalarTexture>) -> Self {
+        self.material.clearcoat_roughness = tex.into();
+        self
+    }
+
+    pub fn transmission(mut self, tex: impl Into<ScalarTexture>) -> Self {
+        self.material.transmission = tex.into();
+        self
+    }
+
+    pub fn ior(mut self, ior: f64) -> Self {
+        self.material.ior = ior;
+        self
+    }
+
+    pub fn emission(mut self, tex: impl Into<TextureKind>) -> Self {
+        self.material.emission = tex.into();
+        self
+    }
+
+    pub fn emission_strength(mut self, strength: f64) -> Self {
+        self.material.emission_strength = strength;
+        self
+    }
+
+    pub fn build(self) -> Principled {
+        self.material
+    }
+}
+
+impl Principled {
+    // Reflection off a microfacet lobe, the Fresnel term being left to the caller
+    fn sample_reflection(
+        sampler: &mut impl Sampler,
+        distribution: &TrowbridgeReitz,
+        wo: &Vec3,
+    ) -> Option<(Vec3, Vec3, f64)> {
+        let (u1, u2) = sampler.get_2d();
+        let wm = distribution.sample_wm(wo, u1, u2);
+        let wi = (-*wo).reflect(&wm);
+        if wi.z <= 0.0 {
+            return None;
+        }
+        Some((wi, wm, distribution.g(wo, &wi) / distribution.g1(wo)))
+    }
+
+    // Microfacet normal, weight and density `sample_reflection` picks `wi`
+    // with
+    fn reflection(
+        distribution: &TrowbridgeReitz,
+        wo: &Vec3,
+        wi: &Vec3,
+    ) -> Option<(Vec3, f64, f64)> {
+        if wi.z <= 0.0 {
+            return None;
+        }
+        let wm = (*wo + *wi).unit_vector();
+        let weight = distribution.g(wo, wi) / distribution.g1(wo);
+        Some((wm, weight, distribution.pdf(wo, &wm) / (4.0 * wo.dot(&wm))))
+    }
+}
+
+impl Material for Principled {
+    fn scatter<T>(
+        &self,
+        sampler: &mut impl Sampler,
+        ray_in: &Ray,
+        hit_record: &HitRecord<T>,
+    ) -> Option<Scatter> {
+        let (u, v, p) = (hit_record.u, hit_record.v, &hit_record.p);
+        let base_color = self.base_color.value(u, v, p);
+        let metallic = self.metallic.value(u, v, p).clamp(0.0, 1.0);
+        let roughness = self.roughness.value(u, v, p).clamp(0.0, 1.0);
+        let clearcoat = self.clearcoat.value(u, v, p).clamp(0.0, 1.0);
+        let transmission = self.transmission.value(u, v, p).clamp(0.0, 1.0);
+
+        let onb = Onb::new(&hit_record.normal);
+        let mut wo = onb.to_local(&-ray_in.dir.unit_vector());
+        wo.z = wo.z.max(1e-6);
+
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let base_luminance = microfacet::luminance(&base_color);
+        let tint = if base_luminance > 0.0 {
+            *base_color / base_luminance
+        } else {
+            white
+        };
+        let distribution = TrowbridgeReitz::new(roughness);
+
+        let scatter = |wi: Vec3, attenuation: Vec3| Scatter {
+            attenuation: attenuation.into(),
+            scattered: ray_in.spawn(hit_record.p, onb.transform(&wi)),
+        };
+
+        // Clearcoat sits on top of everything else, picking it with the
+        // probability of its Fresnel reflectance leaves the rest weighted by
+        // the transmitted energy.
+        let clearcoat_fresnel = clearcoat * microfacet::fresnel_schlick(0.04 * white, wo.z).x;
+        if sampler.get_1d() < clearcoat_fresnel {
+            let coat = TrowbridgeReitz::new(self.clearcoat_roughness.value(u, v, p));
+            let (wi, _, weight) = Self::sample_reflection(sampler, &coat, &wo)?;
+            return Some(scatter(wi, white * weight));
+        }
+
+        // Conductor
+        if sampler.get_1d() < metallic {
+            let (wi, wm, weight) = Self::sample_reflection(sampler, &distribution, &wo)?;
+            let fresnel = microfacet::fresnel_schlick(*base_color, wo.dot(&wm));
+            return Some(scatter(wi, fresnel * weight));
+        }
+
+        // Rough glass
+        if sampler.get_1d() < transmission {
+            let eta = if hit_record.front_face {
+                self.ior
+            } else {
+                1.0 / self.ior
+            };
+            let (u1, u2) = sampler.get_2d();
+            let wm = distribution.sample_wm(&wo, u1, u2);
+            let cos_theta_o = wo.dot(&wm);
+            let reflect = sampler.get_1d() < microfacet::fresnel_dielectric(cos_theta_o, eta);
+            let (wi, attenuation) = if reflect {
+                ((-wo).reflect(&wm), white)
+            } else {
+                ((-wo).refract(&wm, 1.0 / eta), *base_color)
+            };
+            if reflect == (wi.z <= 0.0) {
+                return None;
+            }
+            let weight = distribution.g(&wo, &wi) / distribution.g1(&wo);
+            return Some(scatter(wi, attenuation * weight));
+        }
+
+        // Opaque dielectric, a specular layer over a diffuse base
+        let specular = self.specular.value(u, v, p);
+        let specular_tint = self.specular_tint.value(u, v, p);
+        let f0 = 0.08 * specular * (white * (1.0 - specular_tint) + tint * specular_tint);
+        let specular_probability = microfacet::luminance(&microfacet::fresnel_schlick(f0, wo.z));
+        if sampler.get_1d() < specular_probability {
+            let (wi, wm, weight) = Self::sample_reflection(sampler, &distribution, &wo)?;
+            let fresnel = microfacet::fresnel_schlick(f0, wo.dot(&wm));
+            return Some(scatter(wi, fresnel * weight / specular_probability));
+        }
+
+        // Burley diffuse with sheen, cosine sampled. Choosing this lobe with
+        // the probability of the specular transmission accounts for it.
+        let wi = Vec3::sample_cosine_direction(sampler.get_2d());
+        let cos_theta_d = wi.dot(&(wi + wo).unit_vector());
+        let fd90 = 0.5 + 2.0 * roughness * cos_theta_d * cos_theta_d;
+        let fd = (1.0 + (fd90 - 1.0) * microfacet::schlick_weight(wi.z))
+            * (1.0 + (fd90 - 1.0) * microfacet::schlick_weight(wo.z));
+
+        let sheen = self.sheen.value(u, v, p);
+        let sheen_tint = self.sheen_tint.value(u, v, p);
+        let sheen_color = white * (1.0 - sheen_tint) + tint * sheen_tint;
+        let sheen_term = sheen * microfacet::schlick_weight(cos_theta_d) * sheen_color;
+
+        Some(scatter(wi, *base_color * fd + sheen_term))
+    }
+
+    fn evaluate<T>(&self, hit_record: &HitRecord<T>, wo: &Vec3, wi: &Vec3) -> Option<(Color, f64)> {
+        let (u, v, p) = (hit_record.u, hit_record.v, &hit_record.p);
+        let base_color = self.base_color.value(u, v, p);
+        let metallic = self.metallic.value(u, v, p).clamp(0.0, 1.0);
+        let roughness = self.roughness.value(u, v, p).clamp(0.0, 1.0);
+        let clearcoat = self.clearcoat.value(u, v, p).clamp(0.0, 1.0);
+        let transmission = self.transmission.value(u, v, p).clamp(0.0, 1.0);
+
+        let onb = Onb::new(&hit_record.normal);
+        let mut wo = onb.to_local(wo);
+        wo.z = wo.z.max(1e-6);
+        let wi = onb.to_local(wi);
+        if wi.z == 0.0 {
+            return Some((Color::default(), 0.0));
+        }
+
+        let white = Vec3::new(1.0, 1.0, 1.0);
+        let base_luminance = microfacet::luminance(&base_color);
+        let tint = if base_luminance > 0.0 {
+            *base_color / base_luminance
+        } else {
+            white
+        };
+        let distribution = TrowbridgeReitz::new(roughness);
+
+        // Each lobe adds its attenuation times its density, weighted by the
+        // probability of `scatter` picking it, which sums up to the BSDF
+        // times the cosine.
+        let mut f_cos = Vec3::default();
+        let mut pdf = 0.0;
+        let mut lobe = |probability: f64, attenuation: Vec3, density: f64| {
+            if probability > 0.0 && density > 0.0 {
+                f_cos += attenuation * (probability * density);
+                pdf += probability * density;
+            }
+        };
+
+        let clearcoat_fresnel = clearcoat * microfacet::fresnel_schlick(0.04 * white, wo.z).x;
+        let coat = TrowbridgeReitz::new(self.clearcoat_roughness.value(u, v, p));
+        if let Some((_, weight, density)) = Self::reflection(&coat, &wo, &wi) {
+            lobe(clearcoat_fresnel, white * weight, density);
+        }
+        let mut remaining = 1.0 - clearcoat_fresnel;
+
+        // Conductor
+        if let Some((wm, weight, density)) = Self::reflection(&distribution, &wo, &wi) {
+            let fresnel = microfacet::fresnel_schlick(*base_color, wo.dot(&wm));
+            lobe(remaining * metallic, fresnel * weight, density);
+        }
+        remaining *= 1.0 - metallic;
+
+        // Rough glass, refracting through the generalized half vector
+        let eta = if hit_record.front_face {
+            self.ior
+        } else {
+            1.0 / self.ior
+        };
+        if wi.z > 0.0 {
+            if let Some((wm, weight, density)) = Self::reflection(&distribution, &wo, &wi) {
+                let fresnel = microfacet::fresnel_dielectric(wo.dot(&wm), eta);
+                lobe(remaining * transmission, white * weight, fresnel * density);
+            }
+        } else {
+            let mut wm = (wo + wi * eta).unit_vector();
+            if wm.z < 0.0 {
+                wm = -wm;
+            }
+            if wo.dot(&wm) > 0.0 && wi.dot(&wm) < 0.0 {
+                let fresnel = microfacet::fresnel_dielectric(wo.dot(&wm), eta);
+                let dwm_dwi = wi.dot(&wm).abs() / (wi.dot(&wm) + wo.dot(&wm) / eta).powi(2);
+                let density = (1.0 - fresnel) * distribution.pdf(&wo, &wm) * dwm_dwi;
+                let weight = distribution.g(&wo, &wi) / distribution.g1(&wo);
+                lobe(remaining * transmission, *base_color * weight, density);
+            }
+        }
+        remaining *= 1.0 - transmission;
+
+        // Opaque dielectric
+        let specular = self.specular.value(u, v, p);
+        let specular_tint = self.specular_tint.value(u, v, p);
+        let f0 = 0.08 * specular * (white * (1.0 - specular_tint) + tint * specular_tint);
+        let specular_probability = microfacet::luminance(&microfacet::fresnel_schlick(f0, wo.z));
+        if let Some((wm, weight, density)) = Self::reflection(&distribution, &wo, &wi) {
+            let fresnel = microfacet::fresnel_schlick(f0, wo.dot(&wm));
+            lobe(
+                remaining * specular_probability,
+                fresnel * weight / specular_probability,
+                density,
+            );
+        }
+
+        if wi.z > 0.0 {
+            let cos_theta_d = wi.dot(&(wi + wo).unit_vector());
+            let fd90 = 0.5 + 2.0 * roughness * cos_theta_d * cos_theta_d;
+            let fd = (1.0 + (fd90 - 1.0) * microfacet::schlick_weight(wi.z))
+                * (1.0 + (fd90 - 1.0) * microfacet::schlick_weight(wo.z));
+
+            let sheen = self.sheen.value(u, v, p);
+            let sheen_tint = self.sheen_tint.value(u, v, p);
+            let sheen_color = white * (1.0 - sheen_tint) + tint * sheen_tint;
+            let sheen_term = sheen * microfacet::schlick_weight(cos_theta_d) * sheen_color;
+
+            lobe(
+                remaining * (1.0 - specular_probability),
+                *base_color * fd + sheen_term,
+                wi.z / PI,
+            );
+        }
+
+        Some(((f_cos / wi.z.abs()).into(), pdf))
+    }
+
+    fn emitted<T>(&self, hit_record: &HitRecord<T>) -> Color {
+        let emission = self
+            .emission
+            .value(hit_record.u, hit_record.v, &hit_record.p);
+        (*emission * self.emission_strength).into()
+    }
+
+    fn uniform_emission(&self) -> Option<Color> {
+        let TextureKind::Solid(emission) = &self.emission else {
+            return None;
+        };
+        let radiance = **emission * self.emission_strength;
+        (!radiance.near_zero()).then(|| radiance.into())
+    }
+
+    fn albedo<T>(&self, hit_record: &HitRecord<T>) -> Color {
+        self.base_color
+            .value(hit_record.u, hit_record.v, &hit_record.p)
+    }
+
+    fn relative_ior<T>(&self, hit_record: &HitRecord<T>) -> f64 {
+        if hit_record.front_face {
+            self.ior
+        } else {
+            1.0 / self.ior
+        }
+    }
 }
 
 impl Material for MaterialKind {
     fn scatter<T>(
         &self,
-        rng: &mut impl Rng,
+        sampler: &mut impl Sampler,
         ray_in: &Ray,
         hit_record: &HitRecord<T>,
     ) -> Option<Scatter> {
         match self {
-            MaterialKind::Lambertian(mat) => mat.scatter(rng, ray_in, hit_record),
-            MaterialKind::Metal(mat) => mat.scatter(rng, ray_in, hit_record),
-            MaterialKind::Dielectric(mat) => mat.scatter(rng, ray_in, hit_record),
+            MaterialKind::Lambertian(mat) => mat.scatter(sampler, ray_in, hit_record),
+            MaterialKind::Metal(mat) => mat.scatter(sampler, ray_in, hit_record),
+            MaterialKind::Dielectric(mat) => mat.scatter(sampler, ray_in, hit_record),
+            MaterialKind::Principled(mat) => mat.scatter(sampler, ray_in, hit_record),
+        }
+    }
+
+    fn emitted<T>(&self, hit_record: &HitRecord<T>) -> Color {
+        match self {
+            MaterialKind::Principled(mat) => mat.emitted(hit_record),
+            _ => Color::default(),
+        }
+    }
+
+    fn uniform_emission(&self) -> Option<Color> {
+        match self {
+            MaterialKind::Principled(mat) => mat.uniform_emission(),
+            _ => None,
+        }
+    }
+
+    fn albedo<T>(&self, hit_record: &HitRecord<T>) -> Color {
+        match self {
+            MaterialKind::Lambertian(mat) => mat.albedo(hit_record),
+            MaterialKind::Metal(mat) => mat.albedo(hit_record),
+            MaterialKind::Dielectric(mat) => mat.albedo(hit_record),
+            MaterialKind::Principled(mat) => mat.albedo(hit_record),
+        }
+    }
+
+    fn is_specular(&self) -> bool {
+        match self {
+            MaterialKind::Lambertian(mat) => mat.is_specular(),
+            MaterialKind::Metal(mat) => mat.is_specular(),
+            MaterialKind::Dielectric(mat) => mat.is_specular(),
+            MaterialKind::Principled(mat) => mat.is_specular(),
+        }
+    }
+
+    fn relative_ior<T>(&self, hit_record: &HitRecord<T>) -> f64 {
+        match self {
+            MaterialKind::Lambertian(mat) => mat.relative_ior(hit_record),
+            MaterialKind::Metal(mat) => mat.relative_ior(hit_record),
+            MaterialKind::Dielectric(mat) => mat.relative_ior(hit_record),
+            MaterialKind::Principled(mat) => mat.relative_ior(hit_record),
+        }
+    }
+
+    fn evaluate<T>(&self, hit_record: &HitRecord<T>, wo: &Vec3, wi: &Vec3) -> Option<(Color, f64)> {
+        match self {
+            MaterialKind::Lambertian(mat) => mat.evaluate(hit_record, wo, wi),
+            MaterialKind::Metal(mat) => mat.evaluate(hit_record, wo, wi),
+            MaterialKind::Dielectric(mat) => mat.evaluate(hit_record, wo, wi),
+            MaterialKind::Principled(mat) => mat.evaluate(hit_record, wo, wi),
         }
     }
 }
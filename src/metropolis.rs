@@ -0,0 +1,401 @@
+use std::f64::consts::PI;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use rayon::prelude::*;
+
+use crate::{
+    camera::Camera,
+    film::{Film, FilmSample, FilmTile},
+    filter::Filter,
+    hittable::Hittable,
+    material::Material,
+    microfacet::luminance,
+    sampler::Sampler,
+    vec3::Vec3,
+};
+
+// Stream bit of the random numbers of the chains, keeping them apart from
+// the sample vectors of the bootstrap paths, numbered by path
+const CHAIN_STREAM: u64 = 1 << 63;
+
+// Mutations every chain makes before their splats are added to the image, in
+// chain order so the sums don't depend on scheduling
+const ROUND_MUTATIONS: u64 = 256;
+
+/// Primary sample space Metropolis light transport (Kelemen et al. 2002).
+/// Markov chains of camera samples are mutated, each one a vector of the
+/// random numbers that the camera and its integrator turn into a path, and
+/// visit image regions in proportion to their brightness. Paths that are hard
+/// to find, such as light coming through a narrow gap, are then explored
+/// around once one of them is found.
+#[derive(Debug, Clone)]
+pub struct Metropolis {
+    mutations_per_pixel: u32,
+    bootstrap_samples: u32,
+    chains: u32,
+    large_step_probability: f64,
+    sigma: f64,
+}
+
+impl Default for Metropolis {
+    fn default() -> Self {
+        Self {
+            mutations_per_pixel: 100,
+            bootstrap_samples: 100_000,
+            chains: 1000,
+            large_step_probability: 0.3,
+            sigma: 0.01,
+        }
+    }
+}
+
+impl Metropolis {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mutations over all chains, per pixel of the image.
+    pub fn mutations_per_pixel(mut self, mutations: u32) -> Self {
+        self.mutations_per_pixel = mutations.max(1);
+        self
+    }
+
+    /// Independent samples estimating the overall brightness of the image,
+    /// which the chains start from.
+    pub fn bootstrap_samples(mut self, samples: u32) -> Self {
+        self.bootstrap_samples = samples.max(1);
+        self
+    }
+
+    /// Chains run in parallel, more of them spread the start-up bias of
+    /// each over the image.
+    pub fn chains(mut self, chains: u32) -> Self {
+        self.chains = chains.max(1);
+        self
+    }
+
+    /// Probability of a mutation replacing the whole sample vector with new
+    /// random numbers instead of perturbing it.
+    pub fn large_step_probability(mut self, probability: f64) -> Self {
+        self.large_step_probability = probability;
+        self
+    }
+
+    /// Standard deviation of the small perturbations of each random number.
+    pub fn sigma(mut self, sigma: f64) -> Self {
+        self.sigma = sigma;
+        self
+    }
+
+    /// Renders the image seen by `camera`, with the camera's integrator
+    /// tracing the paths. Every random number is derived from `seed`, the
+    /// same seed always gives the same image whatever the number of threads.
+    pub fn render<M: Material>(
+        &self,
+        seed: u64,
+        camera: &Camera,
+        world: &(impl Hittable<M> + Sync + ?Sized),
+    ) -> Film {
+        let (width, height) = (camera.image_width, camera.image_height);
+        let mut film = Film::new(width, height);
+
+        let contributions: Vec<f64> = (0..self.bootstrap_samples)
+            .into_par_iter()
+            .map(|i| {
+                let mut sampler = MutatingSampler::new(seed, i as u64, self);
+                PathSample::new(camera, world, &mut sampler).contribution
+            })
+            .collect();
+        let cdf: Vec<f64> = contributions
+            .iter()
+            .scan(0.0, |sum, c| {
+                *sum += c;
+                Some(*sum)
+            })
+            .collect();
+        let total = cdf.last().copied().unwrap_or(0.0);
+        if total <= 0.0 {
+            return film;
+        }
+
+        // Chains start from bootstrap paths picked in proportion to their
+        // contribution, replaying their random numbers.
+        let mutations = self.mutations_per_pixel as u64 * (width * height) as u64;
+        let mut chains: Vec<Chain> = (0..self.chains as u64)
+            .into_par_iter()
+            .map(|c| {
+                let mut rng = stream_rng(seed, CHAIN_STREAM | (2 * c));
+                let u = rng.random::<f64>() * total;
+                let start = cdf.partition_point(|&sum| sum <= u).min(cdf.len() - 1);
+                let mut sampler = MutatingSampler::new(seed, start as u64, self);
+                let current = PathSample::new(camera, world, &mut sampler);
+                // Chains starting from the same path mutate it their own way.
+                sampler.rng = stream_rng(seed, CHAIN_STREAM | (2 * c + 1));
+                let remaining =
+                    mutations / self.chains as u64 + u64::from(c < mutations % self.chains as u64);
+                Chain {
+                    rng,
+                    sampler,
+                    current,
+                    remaining,
+                }
+            })
+            .collect();
+
+        let mut sums = vec![(Vec3::default(), Vec3::default()); (width * height) as usize];
+        let rounds = mutations
+            .div_ceil(self.chains as u64)
+            .div_ceil(ROUND_MUTATIONS);
+        for round in 0..rounds {
+            let splats: Vec<Vec<Splat>> = chains
+                .par_iter_mut()
+                .map(|chain| chain.run(camera, world, ROUND_MUTATIONS))
+                .collect();
+            for splat in splats.iter().flatten() {
+                let sum = &mut sums[splat.pixel];
+                sum.0 += splat.direct;
+                sum.1 += splat.indirect;
+            }
+            eprint!("\rRound {}/{rounds}   ", round + 1);
+        }
+        eprint!("\rDone.                                          \n");
+
+        // Each mutation splats a total luminance of one, scaled to the mean
+        // contribution of a sample.
+        let scale = total / self.bootstrap_samples as f64 / self.mutations_per_pixel as f64;
+        let mut tile = FilmTile::new(Filter::Box { radius: 0.5 }, 0, 0, width, height);
+        for j in 0..height {
+            for i in 0..width {
+                let (direct, indirect) = sums[(j * width + i) as usize];
+                let sample = FilmSample {
+                    direct: direct * scale,
+                    indirect: indirect * scale,
+                    surface: None,
+                };
+                tile.add_sample((i, j), (i as f64 + 0.5, j as f64 + 0.5), &sample);
+            }
+        }
+        film.merge_tile(&tile);
+        film
+    }
+}
+
+fn stream_rng(seed: u64, stream: u64) -> ChaCha12Rng {
+    let mut rng = ChaCha12Rng::seed_from_u64(seed);
+    rng.set_stream(stream);
+    rng
+}
+
+/// Camera sample traced from the current sample vector of a chain.
+#[derive(Debug, Clone)]
+struct PathSample {
+    pixel: usize,
+    direct: Vec3,
+    indirect: Vec3,
+    // Luminance of the light carried, which the chains sample in proportion to
+    contribution: f64,
+}
+
+impl PathSample {
+    fn new<M: Material>(
+        camera: &Camera,
+        world: &(impl Hittable<M> + ?Sized),
+        sampler: &mut MutatingSampler,
+    ) -> Self {
+        let (width, height) = (camera.image_width, camera.image_height);
+        sampler.start_pixel_sample((0, 0), 0);
+        let (u, v) = sampler.get_2d();
+        let position = (u * width as f64, v * height as f64);
+        let sample = camera.sample_pixel(sampler, world, position);
+        let (i, j) = (
+            (position.0 as u32).min(width - 1),
+            (position.1 as u32).min(height - 1),
+        );
+        let contribution = luminance(&(sample.direct + sample.indirect));
+        Self {
+            pixel: (j * width + i) as usize,
+            direct: sample.direct,
+            indirect: sample.indirect,
+            contribution: if contribution.is_finite() {
+                contribution.max(0.0)
+            } else {
+                0.0
+            },
+        }
+    }
+
+    /// Light of the sample normalized to a luminance of one and weighted by
+    /// `weight`, nothing for samples carrying no light.
+    fn splat(&self, weight: f64) -> Option<Splat> {
+        if self.contribution <= 0.0 || weight <= 0.0 {
+            return None;
+        }
+        let scale = weight / self.contribution;
+        Some(Splat {
+            pixel: self.pixel,
+            direct: self.direct * scale,
+            indirect: self.indirect * scale,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Splat {
+    pixel: usize,
+    direct: Vec3,
+    indirect: Vec3,
+}
+
+/// Markov chain of camera samples.
+#[derive(Debug, Clone)]
+struct Chain {
+    rng: ChaCha12Rng,
+    sampler: MutatingSampler,
+    current: PathSample,
+    remaining: u64,
+}
+
+impl Chain {
+    /// Makes up to `mutations` of the mutations left, returning the splats.
+    /// Both the current and the proposed sample are splatted, weighted by
+    /// the probability of staying and of moving.
+    fn run<M: Material>(
+        &mut self,
+        camera: &Camera,
+        world: &(impl Hittable<M> + ?Sized),
+        mutations: u64,
+    ) -> Vec<Splat> {
+        let mutations = mutations.min(self.remaining);
+        self.remaining -= mutations;
+        let mut splats = Vec::with_capacity(2 * mutations as usize);
+        for _ in 0..mutations {
+            self.sampler.start_iteration();
+            let proposed = PathSample::new(camera, world, &mut self.sampler);
+            let accept = if self.current.contribution > 0.0 {
+                (proposed.contribution / self.current.contribution).min(1.0)
+            } else {
+                1.0
+            };
+            splats.extend(proposed.splat(accept));
+            splats.extend(self.current.splat(1.0 - accept));
+
+            if self.rng.random::<f64>() < accept {
+                self.current = proposed;
+                self.sampler.accept();
+            } else {
+                self.sampler.reject();
+            }
+        }
+        splats
+    }
+}
+
+/// Random number of a sample vector with the iteration it last changed at,
+/// and its state before that change.
+#[derive(Debug, Clone, Copy, Default)]
+struct PrimarySample {
+    value: f64,
+    modified: u64,
+    backup: f64,
+    backup_modified: u64,
+}
+
+/// Sampler handing out the random numbers of a sample vector, mutating each
+/// one when first asked for in an iteration so that paths using fewer of them
+/// don't pay for the rest.
+#[derive(Debug, Clone)]
+struct MutatingSampler {
+    rng: ChaCha12Rng,
+    samples: Vec<PrimarySample>,
+    sigma: f64,
+    large_step_probability: f64,
+    iteration: u64,
+    large_step: bool,
+    last_large_step: u64,
+    dimension: usize,
+}
+
+impl MutatingSampler {
+    /// Sampler whose first sample vector is drawn from stream `stream` of
+    /// `seed`, the same stream always giving the same vector.
+    fn new(seed: u64, stream: u64, metropolis: &Metropolis) -> Self {
+        Self {
+            rng: stream_rng(seed, stream),
+            samples: Vec::new(),
+            sigma: metropolis.sigma,
+            large_step_probability: metropolis.large_step_probability,
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+            dimension: 0,
+        }
+    }
+
+    fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.random::<f64>() < self.large_step_probability;
+    }
+
+    fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    /// Puts back the random numbers changed by the rejected iteration.
+    fn reject(&mut self) {
+        for sample in &mut self.samples {
+            if sample.modified == self.iteration {
+                sample.value = sample.backup;
+                sample.modified = sample.backup_modified;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    /// Brings random number `index` up to the current iteration, replaying
+    /// the small steps it missed as a single wider one.
+    fn mutate(&mut self, index: usize) {
+        if index >= self.samples.len() {
+            self.samples.resize(index + 1, PrimarySample::default());
+        }
+        let sample = &mut self.samples[index];
+        if sample.modified < self.last_large_step {
+            sample.value = self.rng.random();
+            sample.modified = self.last_large_step;
+        }
+        sample.backup = sample.value;
+        sample.backup_modified = sample.modified;
+
+        if self.large_step {
+            sample.value = self.rng.random();
+        } else {
+            let steps = (self.iteration - sample.modified) as f64;
+            // Box-Muller transform of two uniform numbers
+            let (u1, u2): (f64, f64) = (1.0 - self.rng.random::<f64>(), self.rng.random());
+            let normal = (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos();
+            sample.value += normal * self.sigma * steps.sqrt();
+            sample.value -= sample.value.floor();
+        }
+        sample.modified = self.iteration;
+    }
+}
+
+impl Sampler for MutatingSampler {
+    /// Chains pick their own pixels, this only goes back to the first
+    /// random number of the sample vector.
+    fn start_pixel_sample(&mut self, _pixel: (u32, u32), _index: u32) {
+        self.dimension = 0;
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.mutate(self.dimension);
+        self.dimension += 1;
+        self.samples[self.dimension - 1].value
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
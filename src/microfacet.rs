@@ -0,0 +1,115 @@
+use std::f64::consts::PI;
+
+use crate::vec3::Vec3;
+
+// All directions are in the local shading frame, with the normal along +Z.
+
+/// Trowbridge-Reitz (GGX) microfacet distribution.
+#[derive(Debug, Clone, Copy)]
+pub struct TrowbridgeReitz {
+    alpha: f64,
+}
+
+impl TrowbridgeReitz {
+    pub fn new(roughness: f64) -> Self {
+        // Perceptual roughness is squared, and kept away from a perfect mirror
+        // which the sampling below cannot represent.
+        Self {
+            alpha: (roughness * roughness).max(1e-3),
+        }
+    }
+
+    pub fn d(&self, wm: &Vec3) -> f64 {
+        let cos2 = wm.z * wm.z;
+        if cos2 <= 0.0 {
+            return 0.0;
+        }
+        let a2 = self.alpha * self.alpha;
+        let denom = cos2 * (a2 - 1.0) + 1.0;
+        a2 / (PI * denom * denom)
+    }
+
+    fn lambda(&self, w: &Vec3) -> f64 {
+        let cos2 = w.z * w.z;
+        if cos2 == 0.0 {
+            return f64::INFINITY;
+        }
+        let tan2 = (1.0 - cos2).max(0.0) / cos2;
+        ((1.0 + self.alpha * self.alpha * tan2).sqrt() - 1.0) / 2.0
+    }
+
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    pub fn g(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density of `sample_wm` for the microfacet normal `wm`.
+    pub fn pdf(&self, wo: &Vec3, wm: &Vec3) -> f64 {
+        if wo.z == 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) / wo.z.abs() * self.d(wm) * wo.dot(wm).abs()
+    }
+
+    /// Samples a microfacet normal visible from `wo` (Heitz 2018).
+    pub fn sample_wm(&self, wo: &Vec3, u1: f64, u2: f64) -> Vec3 {
+        let wo = if wo.z < 0.0 { -*wo } else { *wo };
+        // Transform the view direction to the hemisphere configuration
+        let vh = Vec3::new(self.alpha * wo.x, self.alpha * wo.y, wo.z).unit_vector();
+
+        let lensq = vh.x * vh.x + vh.y * vh.y;
+        let t1 = if lensq > 0.0 {
+            Vec3::new(-vh.y, vh.x, 0.0) / lensq.sqrt()
+        } else {
+            Vec3::new(1.0, 0.0, 0.0)
+        };
+        let t2 = vh.cross(&t1);
+
+        // Sample the projected area of the visible hemisphere
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z);
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+
+        let nh = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * vh;
+        Vec3::new(self.alpha * nh.x, self.alpha * nh.y, nh.z.max(1e-6)).unit_vector()
+    }
+}
+
+pub fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta).clamp(0.0, 1.0).powi(5)
+}
+
+pub fn fresnel_schlick(f0: Vec3, cos_theta: f64) -> Vec3 {
+    f0 + (Vec3::new(1.0, 1.0, 1.0) - f0) * schlick_weight(cos_theta)
+}
+
+/// Unpolarized Fresnel reflectance of a dielectric interface, `eta` being the
+/// ratio of the transmitted over the incident index of refraction.
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let cos_theta_i = cos_theta_i.clamp(-1.0, 1.0);
+    let (cos_theta_i, eta) = if cos_theta_i < 0.0 {
+        (-cos_theta_i, 1.0 / eta)
+    } else {
+        (cos_theta_i, eta)
+    };
+
+    let sin2_theta_t = (1.0 - cos_theta_i * cos_theta_i) / (eta * eta);
+    if sin2_theta_t >= 1.0 {
+        // Total internal reflection
+        return 1.0;
+    }
+    let cos_theta_t = (1.0 - sin2_theta_t).sqrt();
+
+    let r_parl = (eta * cos_theta_i - cos_theta_t) / (eta * cos_theta_i + cos_theta_t);
+    let r_perp = (cos_theta_i - eta * cos_theta_t) / (cos_theta_i + eta * cos_theta_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.0
+}
+
+pub fn luminance(c: &Vec3) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
@@ -0,0 +1,81 @@
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{__m256d, _mm256_setr_pd, _mm256_storeu_pd};
+
+use crate::ray::Ray;
+
+/// Rays traced together by packet queries, as many as fit an AVX register of
+/// doubles.
+pub const PACKET_WIDTH: usize = 4;
+
+/// Rays traced together, with their components also laid out lane by lane
+/// so that box and primitive tests can run on all of them at once.
+#[derive(Debug, Clone)]
+pub struct RayPacket {
+    pub rays: [Ray; PACKET_WIDTH],
+    // Per axis, the component of every ray
+    pub origin: [[f64; PACKET_WIDTH]; 3],
+    pub dir: [[f64; PACKET_WIDTH]; 3],
+    pub inv_dir: [[f64; PACKET_WIDTH]; 3],
+    pub time: [f64; PACKET_WIDTH],
+}
+
+impl RayPacket {
+    pub fn new(rays: [Ray; PACKET_WIDTH]) -> Self {
+        let lanes = |component: fn(&Ray) -> f64| rays.each_ref().map(component);
+        let origin = [
+            lanes(|r| r.origin.x),
+            lanes(|r| r.origin.y),
+            lanes(|r| r.origin.z),
+        ];
+        let dir = [lanes(|r| r.dir.x), lanes(|r| r.dir.y), lanes(|r| r.dir.z)];
+        let inv_dir = dir.map(|axis| axis.map(|d| 1.0 / d));
+        let time = lanes(|r| r.time);
+
+        Self {
+            rays,
+            origin,
+            dir,
+            inv_dir,
+            time,
+        }
+    }
+}
+
+/// Whether packet queries can run their AVX kernels on this CPU. Otherwise
+/// they fall back on testing the rays one at a time, with the same results.
+pub fn has_avx() -> bool {
+    #[cfg(target_arch = "x86_64")]
+    {
+        is_x86_feature_detected!("avx")
+    }
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        false
+    }
+}
+
+/// The lanes of `values` in an AVX register.
+///
+/// # Safety
+///
+/// The CPU must support AVX, as `has_avx` checks.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+pub fn load(values: &[f64; PACKET_WIDTH]) -> __m256d {
+    _mm256_setr_pd(values[0], values[1], values[2], values[3])
+}
+
+/// The lanes of an AVX register.
+///
+/// # Safety
+///
+/// The CPU must support AVX, as `has_avx` checks.
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx")]
+pub fn store(v: __m256d) -> [f64; PACKET_WIDTH] {
+    let mut values = [0.0; PACKET_WIDTH];
+    // SAFETY: `values` has room for the four doubles written, and unaligned
+    // stores are allowed.
+    unsafe { _mm256_storeu_pd(values.as_mut_ptr(), v) };
+    values
+}
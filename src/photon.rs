@@ -0,0 +1,319 @@
+use std::f64::consts::PI;
+
+use rayon::prelude::*;
+
+use crate::{
+    color::Color,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    light::LightList,
+    material::Material,
+    onb::Onb,
+    ray::Ray,
+    sampler::{IndependentSampler, Sampler},
+    vec3::{Point, Vec3},
+};
+
+// Keeps the random numbers of photons apart from those of the camera samples
+// rendered with the same seed
+const SEED_SALT: u64 = 0x9e37_79b9_7f4a_7c15;
+
+// Photons only count on surfaces facing about the same way as the one they
+// are gathered at, so corners don't leak light onto the other wall
+const MIN_NORMAL_COSINE: f64 = 0.9;
+
+/// Light stored where it landed on a rough surface.
+#[derive(Debug, Clone)]
+struct Photon {
+    p: Point,
+    // Normal of the surface it landed on
+    n: Vec3,
+    // Unit vector towards where the light came from
+    wi: Vec3,
+    power: Vec3,
+}
+
+/// Builds caustic photon maps, shooting photons from the lights of a scene
+/// and keeping those that reach a rough surface through mirrors or glass.
+#[derive(Debug, Clone)]
+pub struct PhotonMapBuilder {
+    photons: usize,
+    radius: f64,
+    max_depth: u32,
+    time: f64,
+}
+
+impl Default for PhotonMapBuilder {
+    fn default() -> Self {
+        Self {
+            photons: 100_000,
+            radius: 0.1,
+            max_depth: 10,
+            time: 0.0,
+        }
+    }
+}
+
+impl PhotonMapBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Photons shot from the lights, most of which miss the specular
+    /// surfaces and are not stored.
+    pub fn photons(mut self, photons: usize) -> Self {
+        self.photons = photons;
+        self
+    }
+
+    /// Distance in scene units around a point within which photons are
+    /// gathered. Smaller radii keep caustics sharper but noisier.
+    pub fn radius(mut self, radius: f64) -> Self {
+        self.radius = radius;
+        self
+    }
+
+    /// Most mirrors and glass surfaces a photon goes through.
+    pub fn max_depth(mut self, depth: u32) -> Self {
+        self.max_depth = depth;
+        self
+    }
+
+    /// Scene time photons are traced at, caustics of moving objects are not
+    /// motion blurred.
+    pub fn time(mut self, time: f64) -> Self {
+        self.time = time;
+        self
+    }
+
+    /// Shoots the photons from `lights` over `world`. The same seed always
+    /// gives the same map whatever the number of threads.
+    pub fn build<M: Material>(
+        &self,
+        seed: u64,
+        world: &(impl Hittable<M> + Sync + ?Sized),
+        lights: &LightList,
+    ) -> PhotonMap {
+        let mut photons: Vec<Photon> = (0..self.photons)
+            .into_par_iter()
+            .map_init(
+                || IndependentSampler::new(seed ^ SEED_SALT),
+                |sampler, i| {
+                    sampler.start_pixel_sample((i as u32, (i >> 32) as u32), 0);
+                    self.trace_photon(sampler, world, lights)
+                },
+            )
+            .flatten()
+            .collect();
+        for photon in &mut photons {
+            photon.power /= self.photons as f64;
+        }
+
+        let mut axes = vec![0; photons.len()];
+        build_tree(&mut photons, &mut axes);
+        PhotonMap {
+            photons,
+            axes,
+            radius: self.radius,
+            lights: lights.clone(),
+        }
+    }
+
+    /// Photon from a point of a light picked at random, if it reaches a
+    /// rough surface after going through at least one mirror or glass.
+    fn trace_photon<M: Material>(
+        &self,
+        sampler: &mut impl Sampler,
+        world: &(impl Hittable<M> + ?Sized),
+        lights: &LightList,
+    ) -> Option<Photon> {
+        let light = lights.pick(sampler.get_1d())?;
+        let (p, n) = light.sample_point(sampler.get_2d());
+        let side = if sampler.get_1d() < 0.5 { n } else { -n };
+        let local = Vec3::sample_cosine_direction(sampler.get_2d());
+        // Radiance over the densities of the point and of the direction,
+        // cosine distributed over both sides.
+        let mut power = *light.radiance() * (2.0 * PI * light.area() / lights.pick_pdf());
+        let mut ray = Ray::at_time(p, Onb::new(&side).transform(&local), self.time);
+
+        let mut specular = false;
+        for _ in 0..=self.max_depth {
+            let hit = world.hit(&ray, Interval::new(0.001, f64::INFINITY))?;
+            if !hit.material.is_specular() {
+                // Light reaching it directly is left to path tracing.
+                if !specular || !is_evaluable(&hit) {
+                    return None;
+                }
+                return Some(Photon {
+                    p: hit.p,
+                    n: hit.normal,
+                    wi: -ray.dir.unit_vector(),
+                    power,
+                });
+            }
+
+            let scatter = hit.material.scatter(sampler, &ray, &hit)?;
+            let crossing = hit
+                .material
+                .transmitted_power_scale(&hit, &scatter.scattered.dir);
+            power *= *scatter.attenuation * crossing;
+            ray = scatter.scattered;
+            specular = true;
+        }
+        None
+    }
+}
+
+/// Photons that reached rough surfaces through mirrors or glass, in a kd-tree
+/// for gathering those around a point.
+#[derive(Debug, Clone)]
+pub struct PhotonMap {
+    // In kd-tree order, the middle photon of every range splitting it along
+    // the axis stored at the same index
+    photons: Vec<Photon>,
+    axes: Vec<usize>,
+    radius: f64,
+    lights: LightList,
+}
+
+impl PhotonMap {
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Whether photons were shot from the object with the given ID.
+    pub fn emits(&self, object_id: u32) -> bool {
+        self.lights.by_object(object_id).is_some()
+    }
+
+    /// Caustic light leaving `hit` along the unit vector `wo`, estimated from
+    /// the photons within the gather radius. `None` on surfaces whose
+    /// scattering can't be evaluated, where no photons are stored.
+    pub fn radiance<M: Material>(&self, hit: &HitRecord<M>, wo: &Vec3) -> Option<Color> {
+        if !is_evaluable(hit) {
+            return None;
+        }
+        let radius_squared = self.radius * self.radius;
+        let mut sum = Vec3::default();
+        gather(
+            &self.photons,
+            &self.axes,
+            &hit.p,
+            radius_squared,
+            &mut |photon, distance_squared| {
+                if photon.n.dot(&hit.normal) < MIN_NORMAL_COSINE {
+                    return;
+                }
+                if let Some((f, _)) = hit.material.evaluate(hit, wo, &photon.wi) {
+                    // Cone filter, keeping caustic edges sharper than a disk
+                    let weight = 1.0 - (distance_squared / radius_squared).sqrt();
+                    sum += *f * photon.power * weight;
+                }
+            },
+        );
+        // The cone filter covers a third of the disk.
+        Some((sum * (3.0 / (PI * radius_squared))).into())
+    }
+}
+
+/// Whether the scattering of the hit surface can be evaluated, which holds
+/// for every pair of directions or for none.
+fn is_evaluable<M: Material>(hit: &HitRecord<M>) -> bool {
+    hit.material
+        .evaluate(hit, &hit.normal, &hit.normal)
+        .is_some()
+}
+
+fn coordinate(p: &Point, axis: usize) -> f64 {
+    match axis {
+        0 => p.x,
+        1 => p.y,
+        _ => p.z,
+    }
+}
+
+/// Orders `photons` into a kd-tree, splitting every range at its median
+/// along the axis it spreads the most over.
+fn build_tree(photons: &mut [Photon], axes: &mut [usize]) {
+    if photons.is_empty() {
+        return;
+    }
+    let (min, max) = photons.iter().fold(
+        (
+            Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            -Vec3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+        ),
+        |(min, max), photon| {
+            (
+                Vec3::new(
+                    min.x.min(photon.p.x),
+                    min.y.min(photon.p.y),
+                    min.z.min(photon.p.z),
+                ),
+                Vec3::new(
+                    max.x.max(photon.p.x),
+                    max.y.max(photon.p.y),
+                    max.z.max(photon.p.z),
+                ),
+            )
+        },
+    );
+    let extent = max - min;
+    let axis = if extent.x > extent.y && extent.x > extent.z {
+        0
+    } else if extent.y > extent.z {
+        1
+    } else {
+        2
+    };
+
+    let middle = photons.len() / 2;
+    photons.select_nth_unstable_by(middle, |a, b| {
+        coordinate(&a.p, axis).total_cmp(&coordinate(&b.p, axis))
+    });
+    axes[middle] = axis;
+    let (left, rest) = photons.split_at_mut(middle);
+    let (left_axes, rest_axes) = axes.split_at_mut(middle);
+    build_tree(left, left_axes);
+    build_tree(&mut rest[1..], &mut rest_axes[1..]);
+}
+
+/// Calls `f` with every photon within `sqrt(radius_squared)` of `p` and its
+/// squared distance.
+fn gather(
+    photons: &[Photon],
+    axes: &[usize],
+    p: &Point,
+    radius_squared: f64,
+    f: &mut impl FnMut(&Photon, f64),
+) {
+    if photons.is_empty() {
+        return;
+    }
+    let middle = photons.len() / 2;
+    let photon = &photons[middle];
+    let axis = axes[middle];
+    let offset = coordinate(p, axis) - coordinate(&photon.p, axis);
+    let left = (&photons[..middle], &axes[..middle]);
+    let right = (&photons[middle + 1..], &axes[middle + 1..]);
+    let (near, far) = if offset < 0.0 {
+        (left, right)
+    } else {
+        (right, left)
+    };
+
+    gather(near.0, near.1, p, radius_squared, f);
+    // The far side only matters when the sphere reaches over the splitting
+    // plane.
+    if offset * offset < radius_squared {
+        let distance_squared = (photon.p - *p).length_squared();
+        if distance_squared < radius_squared {
+            f(photon, distance_squared);
+        }
+        gather(far.0, far.1, p, radius_squared, f);
+    }
+}
@@ -0,0 +1,106 @@
+use std::time::Duration;
+
+use crate::{color::Color, microfacet::luminance, vec3::Vec3};
+
+/// Running estimate of a pixel, updated one sample at a time with Welford's
+/// algorithm so the variance stays accurate over many samples.
+#[derive(Debug, Clone, Default)]
+pub struct PixelEstimate {
+    samples: u32,
+    mean: Vec3,
+    mean_luminance: f64,
+    // Sum of squared deviations of the luminance from its mean
+    m2: f64,
+}
+
+impl PixelEstimate {
+    pub fn add(&mut self, sample: Vec3) {
+        self.samples += 1;
+        let n = self.samples as f64;
+        self.mean += (sample - self.mean) / n;
+
+        let l = luminance(&sample);
+        let delta = l - self.mean_luminance;
+        self.mean_luminance += delta / n;
+        self.m2 += delta * (l - self.mean_luminance);
+    }
+
+    /// Adds the samples of `other`, combining the two with Chan's formula so
+    /// that the variance is the same as if they had been added one by one.
+    pub fn merge(&mut self, other: &PixelEstimate) {
+        if other.samples == 0 {
+            return;
+        }
+        let (na, nb) = (self.samples as f64, other.samples as f64);
+        let n = na + nb;
+        self.mean += (other.mean - self.mean) * (nb / n);
+
+        let delta = other.mean_luminance - self.mean_luminance;
+        self.mean_luminance += delta * nb / n;
+        self.m2 += other.m2 + delta * delta * na * nb / n;
+        self.samples += other.samples;
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    pub fn mean(&self) -> Color {
+        self.mean.into()
+    }
+
+    /// Sample variance of the luminance, infinite until two samples are in.
+    pub fn variance(&self) -> f64 {
+        if self.samples < 2 {
+            return f64::INFINITY;
+        }
+        self.m2 / (self.samples - 1) as f64
+    }
+
+    /// Estimated variance of the mean, shrinking as samples accumulate.
+    pub fn variance_of_mean(&self) -> f64 {
+        self.variance() / self.samples as f64
+    }
+
+    /// Standard error of the mean luminance relative to the luminance itself.
+    /// Dark pixels are measured against a floor, otherwise their noise would
+    /// never look small enough.
+    pub fn relative_error(&self) -> f64 {
+        self.variance_of_mean().sqrt() / self.mean_luminance.max(RELATIVE_ERROR_FLOOR)
+    }
+}
+
+// Luminance below which errors are taken as absolute rather than relative
+const RELATIVE_ERROR_FLOOR: f64 = 0.01;
+
+/// State of a progressive render, handed to the callback after every pass.
+#[derive(Debug)]
+pub struct Progress<'a> {
+    pub pass: u32,
+    pub samples_per_pixel: u32,
+    pub elapsed: Duration,
+    // Largest variance of the mean over all pixels
+    pub max_variance: f64,
+    // Pixels still taking samples, all of them unless sampling is adaptive
+    pub active_pixels: usize,
+    pub image: &'a [Color],
+    // Samples taken by each pixel so far
+    pub sample_counts: &'a [u32],
+}
+
+/// False colour view of per-pixel sample counts, blue for the fewest samples
+/// through green to red for the most.
+pub fn sample_heatmap(sample_counts: &[u32]) -> Vec<Color> {
+    let max = sample_counts.iter().copied().max().unwrap_or(0).max(1) as f64;
+    sample_counts
+        .iter()
+        .map(|&count| heatmap_color(count as f64 / max))
+        .collect()
+}
+
+/// Blue at 0 through green to red at 1.
+pub fn heatmap_color(t: f64) -> Color {
+    let r = (2.0 * t - 1.0).clamp(0.0, 1.0);
+    let b = (1.0 - 2.0 * t).clamp(0.0, 1.0);
+    Color::new(r, 1.0 - r - b, b)
+}
@@ -0,0 +1,110 @@
+use crate::{
+    aabb::AABB,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    ray::Ray,
+    texture::{AlphaMask, SurfaceMap},
+    vec3::{Point, Vec3},
+};
+
+/// Parallelogram spanned by `u` and `v` from the corner `q`.
+pub struct Quad<'a, T> {
+    q: Point,
+    u: Vec3,
+    v: Vec3,
+    w: Vec3,
+    normal: Vec3,
+    d: f64,
+    material: &'a T,
+    bbox: AABB,
+    surface_map: Option<&'a SurfaceMap>,
+    alpha_mask: Option<&'a AlphaMask>,
+}
+
+impl<'a, T> Quad<'a, T> {
+    pub fn new(q: Point, u: Vec3, v: Vec3, material: &'a T) -> Self {
+        let n = u.cross(&v);
+        let normal = n.unit_vector();
+        let d = normal.dot(&q);
+        let w = n / n.dot(&n);
+
+        // Compute the bounding box of all four vertices.
+        let bbox_diagonal1 = AABB::new_from_points(q, q + u + v);
+        let bbox_diagonal2 = AABB::new_from_points(q + u, q + v);
+        let mut bbox = AABB::new_from_boxes(&bbox_diagonal1, &bbox_diagonal2);
+        bbox.pad_to_minimums();
+
+        Self {
+            q,
+            u,
+            v,
+            w,
+            normal,
+            d,
+            material,
+            bbox,
+            surface_map: None,
+            alpha_mask: None,
+        }
+    }
+
+    /// Perturbs the shading normal with a normal or bump map.
+    pub fn with_surface_map(mut self, surface_map: &'a SurfaceMap) -> Self {
+        self.surface_map = Some(surface_map);
+        self
+    }
+
+    /// Cuts out the parts of the quad where the mask is transparent.
+    pub fn with_alpha_mask(mut self, alpha_mask: &'a AlphaMask) -> Self {
+        self.alpha_mask = Some(alpha_mask);
+        self
+    }
+}
+
+impl<T> Hittable<T> for Quad<'_, T> {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_, T>> {
+        let denom = self.normal.dot(&ray.dir);
+
+        // No hit if the ray is parallel to the plane.
+        if denom.abs() < 1e-8 {
+            return None;
+        }
+
+        // Return false if the hit point parameter t is outside the ray interval.
+        let t = (self.d - self.normal.dot(&ray.origin)) / denom;
+        if !ray_t.contains(t) {
+            return None;
+        }
+
+        // Determine if the hit point lies within the planar shape using its plane coordinates.
+        let p = ray.at(t);
+        let planar_hitpt_vector = p - self.q;
+        let alpha = self.w.dot(&planar_hitpt_vector.cross(&self.v));
+        let beta = self.w.dot(&self.u.cross(&planar_hitpt_vector));
+
+        let unit_interval = Interval::new(0.0, 1.0);
+        if !unit_interval.contains(alpha) || !unit_interval.contains(beta) {
+            return None;
+        }
+        if self
+            .alpha_mask
+            .is_some_and(|mask| !mask.is_opaque(alpha, beta, &p))
+        {
+            return None;
+        }
+
+        let mut rec = HitRecord::new(ray, p, t, self.normal, (alpha, beta), self.material);
+        rec.dpdu = self.u;
+        rec.dpdv = self.v;
+
+        if let Some(surface_map) = self.surface_map {
+            let normal = surface_map.shading_normal(&rec, &self.normal);
+            rec.set_shading_normal(normal);
+        }
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bbox.clone()
+    }
+}
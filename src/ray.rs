@@ -1,10 +1,15 @@
-use crate::vec3::{Point, Vec3};
+use crate::{
+    spectrum::SampledWavelengths,
+    vec3::{Point, Vec3},
+};
 
 #[derive(Clone, PartialEq, Debug, Default)]
 pub struct Ray {
     pub dir: Vec3,
     pub origin: Point,
     pub time: f64,
+    // Wavelengths carried by the path when rendering spectrally
+    pub lambda: Option<SampledWavelengths>,
 }
 
 impl Ray {
@@ -13,11 +18,28 @@ impl Ray {
             dir,
             origin,
             time: 0.0,
+            lambda: None,
         }
     }
 
     pub fn at_time(origin: Point, dir: Vec3, time: f64) -> Self {
-        Self { dir, origin, time }
+        Self {
+            dir,
+            origin,
+            time,
+            lambda: None,
+        }
+    }
+
+    /// Continues the path from `origin` towards `dir`, keeping the time and
+    /// wavelengths of this ray.
+    pub fn spawn(&self, origin: Point, dir: Vec3) -> Self {
+        Self {
+            dir,
+            origin,
+            time: self.time,
+            lambda: self.lambda,
+        }
     }
 
     pub fn at(&self, t: f64) -> Point {
@@ -0,0 +1,657 @@
+use std::sync::OnceLock;
+
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+
+/// Source of the sample values of a camera path. Every call consumes the
+/// next dimension (or two) of the current pixel sample, so the same call
+/// sequence always lines up with the same dimensions.
+pub trait Sampler {
+    /// Moves to sample `index` of `pixel`, restarting at the first dimension.
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32);
+
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+/// Sample distributions the camera can render with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SamplePattern {
+    /// Independent uniform random numbers.
+    #[default]
+    Independent,
+    /// Jittered strata over the samples of a pixel, shuffled per dimension.
+    Stratified,
+    /// Owen scrambled Halton sequence, falls back to random numbers past the
+    /// prime table.
+    Halton,
+    /// Owen scrambled and shuffled Sobol (0,2) sequence in every pair of
+    /// dimensions.
+    Sobol,
+    /// Sobol points shared by all pixels, each pixel offset by a blue noise
+    /// mask so that the remaining error looks like blue noise.
+    BlueNoise,
+}
+
+// Each sample owns 2^32 words of its pixel's random stream, far more than a
+// path of any sensible depth consumes.
+const SAMPLE_WORDS_LOG2: u32 = 32;
+
+/// Random numbers for the current pixel sample, from a stream of its own so
+/// they don't depend on the order pixels and samples are visited in.
+#[derive(Debug, Clone)]
+struct PixelRng(ChaCha12Rng);
+
+impl PixelRng {
+    fn new(seed: u64) -> Self {
+        Self(ChaCha12Rng::seed_from_u64(seed))
+    }
+
+    fn start_pixel_sample(&mut self, (i, j): (u32, u32), index: u32) {
+        self.0.set_stream(((j as u64) << 32) | i as u64);
+        self.0.set_word_pos((index as u128) << SAMPLE_WORDS_LOG2);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.0.random()
+    }
+}
+
+/// Position within the sampled sequence, shared by the structured samplers.
+#[derive(Debug, Clone, Default)]
+struct SampleState {
+    seed: u64,
+    pixel: (u32, u32),
+    index: u32,
+    dimension: u32,
+}
+
+impl SampleState {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.pixel = pixel;
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    /// Hash of the current pixel and dimension, decorrelating the scrambles
+    /// of every dimension and pixel.
+    fn pixel_hash(&self) -> u64 {
+        let (i, j) = self.pixel;
+        mix_bits(self.dimension_hash() ^ mix_bits(((j as u64) << 32) | i as u64))
+    }
+
+    /// Hash of the current dimension only, the same for every pixel.
+    fn dimension_hash(&self) -> u64 {
+        mix_bits(self.seed ^ mix_bits(self.dimension as u64 + 1))
+    }
+
+    fn next(&mut self, dimensions: u32) {
+        self.dimension += dimensions;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IndependentSampler {
+    rng: PixelRng,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: PixelRng::new(seed),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.rng.start_pixel_sample(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.rng.get_1d()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.get_1d(), self.rng.get_1d())
+    }
+}
+
+// Most strata a pixel is split into, longer runs restart the strata
+const MAX_STRATA: u32 = 1 << 16;
+
+#[derive(Debug, Clone)]
+pub struct StratifiedSampler {
+    // Strata along each axis of a 2D dimension, together covering at least
+    // the samples of a pixel
+    x_strata: u32,
+    y_strata: u32,
+    strata: u32,
+    state: SampleState,
+    rng: PixelRng,
+}
+
+impl StratifiedSampler {
+    pub fn new(seed: u64, samples_per_pixel: u32) -> Self {
+        let strata = samples_per_pixel.clamp(1, MAX_STRATA);
+        let x_strata = (strata as f64).sqrt() as u32;
+        let y_strata = strata.div_ceil(x_strata);
+
+        Self {
+            x_strata,
+            y_strata,
+            strata,
+            state: SampleState {
+                seed,
+                ..SampleState::default()
+            },
+            rng: PixelRng::new(seed),
+        }
+    }
+
+    /// Stratum of the current sample among `count`, each dimension visiting
+    /// the strata in its own order.
+    fn stratum(&self, count: u32) -> u32 {
+        let round = self.state.index / count;
+        let hash = self.state.pixel_hash() ^ mix_bits(round as u64);
+        permutation_element(self.state.index % count, count, hash as u32)
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.state.start_pixel_sample(pixel, index);
+        self.rng.start_pixel_sample(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let stratum = self.stratum(self.strata);
+        self.state.next(1);
+        (stratum as f64 + self.rng.get_1d()) / self.strata as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let stratum = self.stratum(self.x_strata * self.y_strata);
+        self.state.next(2);
+        let (x, y) = (stratum % self.x_strata, stratum / self.x_strata);
+        (
+            (x as f64 + self.rng.get_1d()) / self.x_strata as f64,
+            (y as f64 + self.rng.get_1d()) / self.y_strata as f64,
+        )
+    }
+}
+
+const PRIMES: [u64; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+#[derive(Debug, Clone)]
+pub struct HaltonSampler {
+    state: SampleState,
+    rng: PixelRng,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: SampleState {
+                seed,
+                ..SampleState::default()
+            },
+            rng: PixelRng::new(seed),
+        }
+    }
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.state.start_pixel_sample(pixel, index);
+        self.rng.start_pixel_sample(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let u = match PRIMES.get(self.state.dimension as usize) {
+            Some(&base) => owen_scrambled_radical_inverse(
+                base,
+                self.state.index as u64,
+                self.state.pixel_hash(),
+            ),
+            None => self.rng.get_1d(),
+        };
+        self.state.next(1);
+        u
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SobolSampler {
+    state: SampleState,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: SampleState {
+                seed,
+                ..SampleState::default()
+            },
+        }
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.state.start_pixel_sample(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let u = shuffled_scrambled_sobol_2d(self.state.index, self.state.pixel_hash()).0;
+        self.state.next(1);
+        u
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let u = shuffled_scrambled_sobol_2d(self.state.index, self.state.pixel_hash());
+        self.state.next(2);
+        u
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct BlueNoiseSampler {
+    state: SampleState,
+}
+
+impl BlueNoiseSampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: SampleState {
+                seed,
+                ..SampleState::default()
+            },
+        }
+    }
+
+    /// Offset of the current pixel, read from the mask at a position shifted
+    /// differently for every dimension and `axis`.
+    fn offset(&self, axis: u64) -> f64 {
+        let hash = mix_bits(self.state.dimension_hash() ^ axis);
+        let size = BLUE_NOISE_SIZE as u64;
+        let x = (self.state.pixel.0 as u64 + hash) % size;
+        let y = (self.state.pixel.1 as u64 + (hash >> 32)) % size;
+        blue_noise_mask()[(y * size + x) as usize]
+    }
+}
+
+impl Sampler for BlueNoiseSampler {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        self.state.start_pixel_sample(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let u = shuffled_scrambled_sobol_2d(self.state.index, self.state.dimension_hash()).0;
+        let u = (u + self.offset(0)).fract();
+        self.state.next(1);
+        u
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (u, v) = shuffled_scrambled_sobol_2d(self.state.index, self.state.dimension_hash());
+        let u = ((u + self.offset(0)).fract(), (v + self.offset(1)).fract());
+        self.state.next(2);
+        u
+    }
+}
+
+/// Any of the samplers, picked at runtime from a `SamplePattern`.
+#[derive(Debug, Clone)]
+pub enum SamplerKind {
+    Independent(IndependentSampler),
+    Stratified(StratifiedSampler),
+    Halton(HaltonSampler),
+    Sobol(SobolSampler),
+    BlueNoise(BlueNoiseSampler),
+}
+
+impl SamplerKind {
+    pub fn new(pattern: SamplePattern, seed: u64, samples_per_pixel: u32) -> Self {
+        match pattern {
+            SamplePattern::Independent => SamplerKind::Independent(IndependentSampler::new(seed)),
+            SamplePattern::Stratified => {
+                SamplerKind::Stratified(StratifiedSampler::new(seed, samples_per_pixel))
+            }
+            SamplePattern::Halton => SamplerKind::Halton(HaltonSampler::new(seed)),
+            SamplePattern::Sobol => SamplerKind::Sobol(SobolSampler::new(seed)),
+            SamplePattern::BlueNoise => SamplerKind::BlueNoise(BlueNoiseSampler::new(seed)),
+        }
+    }
+}
+
+impl Sampler for SamplerKind {
+    fn start_pixel_sample(&mut self, pixel: (u32, u32), index: u32) {
+        match self {
+            SamplerKind::Independent(s) => s.start_pixel_sample(pixel, index),
+            SamplerKind::Stratified(s) => s.start_pixel_sample(pixel, index),
+            SamplerKind::Halton(s) => s.start_pixel_sample(pixel, index),
+            SamplerKind::Sobol(s) => s.start_pixel_sample(pixel, index),
+            SamplerKind::BlueNoise(s) => s.start_pixel_sample(pixel, index),
+        }
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        match self {
+            SamplerKind::Independent(s) => s.get_1d(),
+            SamplerKind::Stratified(s) => s.get_1d(),
+            SamplerKind::Halton(s) => s.get_1d(),
+            SamplerKind::Sobol(s) => s.get_1d(),
+            SamplerKind::BlueNoise(s) => s.get_1d(),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        match self {
+            SamplerKind::Independent(s) => s.get_2d(),
+            SamplerKind::Stratified(s) => s.get_2d(),
+            SamplerKind::Halton(s) => s.get_2d(),
+            SamplerKind::Sobol(s) => s.get_2d(),
+            SamplerKind::BlueNoise(s) => s.get_2d(),
+        }
+    }
+}
+
+// Smallest digit weight the Halton radical inverse is carried to
+const HALTON_RESOLUTION: f64 = 1.0 / (1u64 << 32) as f64;
+
+// Largest f64 below one
+const ONE_MINUS_EPSILON: f64 = 1.0 - f64::EPSILON / 2.0;
+
+/// 64-bit finalizer spreading every input bit over the output.
+fn mix_bits(mut v: u64) -> u64 {
+    v ^= v >> 31;
+    v = v.wrapping_mul(0x7fb5_d329_728e_a185);
+    v ^= v >> 27;
+    v = v.wrapping_mul(0x81da_def4_bc2d_d44d);
+    v ^= v >> 33;
+    v
+}
+
+/// Element `i` of a random permutation of [0, l) picked by `p`, after
+/// Kensler (2013), "Correlated Multi-Jittered Sampling".
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170_893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929_eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935_fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dc_b303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e50_1cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860_a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            break;
+        }
+    }
+    ((i as u64 + p as u64) % l as u64) as u32
+}
+
+/// Radical inverse of `a` in `base`, every digit permuted depending on the
+/// digits before it, which is Owen scrambling.
+fn owen_scrambled_radical_inverse(base: u64, mut a: u64, hash: u64) -> f64 {
+    let inv_base = 1.0 / base as f64;
+    let mut inv_base_m = 1.0;
+    let mut reversed: u64 = 0;
+    // Digits past 32 bits, the resolution of the Sobol points, are left out.
+    while inv_base_m > HALTON_RESOLUTION {
+        let next = a / base;
+        let digit = a - next * base;
+        let digit_hash = mix_bits(hash ^ reversed) as u32;
+        let digit = permutation_element(digit as u32, base as u32, digit_hash) as u64;
+        reversed = reversed * base + digit;
+        inv_base_m *= inv_base;
+        a = next;
+    }
+    (reversed as f64 * inv_base_m).min(ONE_MINUS_EPSILON)
+}
+
+/// Owen scrambling of the bits of `x` from the most significant one, by
+/// Laine and Karras (2011) as improved in Burley (2020), "Practical Hash-based
+/// Owen Scrambling".
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits();
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50_b47c);
+    x ^= x.wrapping_mul(0xb82f_1e52);
+    x ^= x.wrapping_mul(0xc7af_e638);
+    x ^= x.wrapping_mul(0x8d22_f6e6);
+    x.reverse_bits()
+}
+
+/// First two dimensions of the Sobol sequence, which form a (0,2) sequence.
+fn sobol_2d(index: u32) -> (u32, u32) {
+    let (mut x, mut y) = (0, 0);
+    let mut v = 1 << 31;
+    for bit in 0..32 {
+        if index & (1 << bit) != 0 {
+            x ^= 1 << (31 - bit);
+            y ^= v;
+        }
+        v ^= v >> 1;
+    }
+    (x, y)
+}
+
+/// Point `index` of a 2D Sobol sequence whose order and values are both Owen
+/// scrambled by `hash`, so every hash gives an independent sequence.
+fn shuffled_scrambled_sobol_2d(index: u32, hash: u64) -> (f64, f64) {
+    let index = nested_uniform_scramble(index, hash as u32);
+    let (x, y) = sobol_2d(index);
+    let x = nested_uniform_scramble(x, (hash >> 32) as u32);
+    let y = nested_uniform_scramble(y, mix_bits(hash) as u32);
+
+    let to_unit = |v: u32| (v as f64 / (1u64 << 32) as f64).min(ONE_MINUS_EPSILON);
+    (to_unit(x), to_unit(y))
+}
+
+// Side of the tileable blue noise mask
+const BLUE_NOISE_SIZE: usize = 64;
+
+/// Blue noise mask with values evenly spread over (0,1), built once on first
+/// use.
+fn blue_noise_mask() -> &'static [f64] {
+    static MASK: OnceLock<Vec<f64>> = OnceLock::new();
+    MASK.get_or_init(void_and_cluster)
+}
+
+/// Ulichney's (1993) void and cluster method: pixels are ranked by repeatedly
+/// taking the tightest cluster out of, or filling the largest void in, a point
+/// pattern, measured by Gaussian energy on the torus.
+fn void_and_cluster() -> Vec<f64> {
+    const SIZE: usize = BLUE_NOISE_SIZE;
+    const N: usize = SIZE * SIZE;
+    const SIGMA: f64 = 1.5;
+
+    let wrap = |d: usize| d.min(SIZE - d) as f64;
+    let kernel: Vec<f64> = (0..N)
+        .map(|k| {
+            let (dx, dy) = (wrap(k % SIZE), wrap(k / SIZE));
+            (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+        })
+        .collect();
+
+    struct Pattern<'a> {
+        kernel: &'a [f64],
+        points: Vec<bool>,
+        energy: Vec<f64>,
+    }
+
+    impl Pattern<'_> {
+        fn toggle(&mut self, p: usize) {
+            self.points[p] = !self.points[p];
+            let sign = if self.points[p] { 1.0 } else { -1.0 };
+            let (px, py) = (p % SIZE, p / SIZE);
+            for (q, energy) in self.energy.iter_mut().enumerate() {
+                let dx = (q % SIZE + SIZE - px) % SIZE;
+                let dy = (q / SIZE + SIZE - py) % SIZE;
+                *energy += sign * self.kernel[dy * SIZE + dx];
+            }
+        }
+
+        fn tightest_cluster(&self) -> usize {
+            (0..N)
+                .filter(|&p| self.points[p])
+                .max_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+                .expect("pattern has points")
+        }
+
+        fn largest_void(&self) -> usize {
+            (0..N)
+                .filter(|&p| !self.points[p])
+                .min_by(|&a, &b| self.energy[a].total_cmp(&self.energy[b]))
+                .expect("pattern has voids")
+        }
+    }
+
+    // Random initial pattern with a tenth of the pixels set, then relaxed
+    // until moving the tightest cluster would put it back in place.
+    let mut rng = ChaCha12Rng::seed_from_u64(0);
+    let mut initial = Pattern {
+        kernel: &kernel,
+        points: vec![false; N],
+        energy: vec![0.0; N],
+    };
+    let mut count = 0;
+    while count < N / 10 {
+        let p = rng.random_range(0..N);
+        if !initial.points[p] {
+            initial.toggle(p);
+            count += 1;
+        }
+    }
+    loop {
+        let cluster = initial.tightest_cluster();
+        initial.toggle(cluster);
+        let void = initial.largest_void();
+        initial.toggle(void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    let mut rank = vec![0; N];
+
+    // Points of the initial pattern are ranked from the last one removed.
+    let mut pattern = Pattern {
+        kernel: &kernel,
+        points: initial.points.clone(),
+        energy: initial.energy.clone(),
+    };
+    for r in (0..count).rev() {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        rank[cluster] = r;
+    }
+
+    // The rest in the order they fill the pattern.
+    for r in count..N {
+        let void = initial.largest_void();
+        initial.toggle(void);
+        rank[void] = r;
+    }
+
+    rank.into_iter()
+        .map(|r| (r as f64 + 0.5) / N as f64)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PATTERNS: [SamplePattern; 5] = [
+        SamplePattern::Independent,
+        SamplePattern::Stratified,
+        SamplePattern::Halton,
+        SamplePattern::Sobol,
+        SamplePattern::BlueNoise,
+    ];
+
+    #[test]
+    fn samples_are_in_the_unit_interval() {
+        for pattern in PATTERNS {
+            let mut sampler = SamplerKind::new(pattern, 7, 64);
+            for pixel in [(0, 0), (5, 3), (1023, 767), (u32::MAX, u32::MAX)] {
+                for index in 0..64 {
+                    sampler.start_pixel_sample(pixel, index);
+                    // Past the end of the Halton prime table as well
+                    for _ in 0..40 {
+                        let u = sampler.get_1d();
+                        let (v, w) = sampler.get_2d();
+                        for x in [u, v, w] {
+                            assert!((0.0..1.0).contains(&x), "{pattern:?} {pixel:?} {x}");
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sorted cells of `grid` holding the first `n` samples of `pixel` in its
+    /// first two dimensions.
+    fn cells(pattern: SamplePattern, pixel: (u32, u32), n: u32, grid: (u32, u32)) -> Vec<u32> {
+        let mut sampler = SamplerKind::new(pattern, 7, n);
+        let mut cells: Vec<u32> = (0..n)
+            .map(|index| {
+                sampler.start_pixel_sample(pixel, index);
+                let (u, v) = sampler.get_2d();
+                (v * grid.1 as f64) as u32 * grid.0 + (u * grid.0 as f64) as u32
+            })
+            .collect();
+        cells.sort();
+        cells
+    }
+
+    #[test]
+    fn low_discrepancy_samples_cover_every_stratum() {
+        let every = |n: u32| (0..n).collect::<Vec<_>>();
+        for pixel in [(0, 0), (17, 9)] {
+            // Sobol points form a (0,2)-net, one per cell of any shape of area
+            // 1/16 from their first 16 samples. Blue noise shifts them, which
+            // doesn't keep the cells.
+            for grid in [(16, 1), (4, 4), (1, 16)] {
+                assert_eq!(cells(SamplePattern::Sobol, pixel, 16, grid), every(16));
+            }
+            // Halton's first dimensions are the radical inverses in base 2
+            // and 3.
+            assert_eq!(cells(SamplePattern::Halton, pixel, 16, (16, 1)), every(16));
+            assert_eq!(cells(SamplePattern::Halton, pixel, 9, (1, 9)), every(9));
+            assert_eq!(
+                cells(SamplePattern::Stratified, pixel, 16, (4, 4)),
+                every(16)
+            );
+        }
+    }
+}
@@ -0,0 +1,71 @@
+/// Fraction of the light let through while the shutter is open, which weights
+/// how ray times are distributed over the shutter interval.
+#[derive(Debug, Clone)]
+pub enum ShutterCurve {
+    /// Instantly fully open.
+    Box,
+    /// Opens and closes linearly, each ramp taking `ramp` of the interval.
+    Trapezoid { ramp: f64 },
+    /// Measured curve, see `ShutterCurve::tabulated`.
+    Tabulated(TabulatedCurve),
+}
+
+#[derive(Debug, Clone)]
+pub struct TabulatedCurve {
+    // Normalized cumulative weights at the end of each of the evenly spaced bins
+    cdf: Vec<f64>,
+}
+
+impl ShutterCurve {
+    /// Piecewise constant curve with `weights` evenly spaced over the interval.
+    pub fn tabulated(weights: &[f64]) -> Self {
+        let mut total = 0.0;
+        let mut cdf: Vec<f64> = weights
+            .iter()
+            .map(|w| {
+                total += w.max(0.0);
+                total
+            })
+            .collect();
+        assert!(total > 0.0, "shutter curve must let some light through");
+        for c in &mut cdf {
+            *c /= total;
+        }
+
+        ShutterCurve::Tabulated(TabulatedCurve { cdf })
+    }
+
+    /// Maps a uniform `u` in [0,1) to a fraction of the shutter interval.
+    pub fn sample(&self, u: f64) -> f64 {
+        match self {
+            ShutterCurve::Box => u,
+            ShutterCurve::Trapezoid { ramp } => {
+                let r = ramp.clamp(0.0, 0.5);
+                if r == 0.0 {
+                    return u;
+                }
+                // Invert the cumulative area of the trapezoid, whose total is 1 - r.
+                let a = u * (1.0 - r);
+                if a < r / 2.0 {
+                    (2.0 * r * a).sqrt()
+                } else if a < 1.0 - 1.5 * r {
+                    a + r / 2.0
+                } else {
+                    1.0 - (2.0 * r * (1.0 - r - a)).max(0.0).sqrt()
+                }
+            }
+            ShutterCurve::Tabulated(curve) => {
+                let bins = curve.cdf.len();
+                let idx = curve.cdf.partition_point(|&c| c <= u).min(bins - 1);
+                let start = if idx == 0 { 0.0 } else { curve.cdf[idx - 1] };
+                let width = curve.cdf[idx] - start;
+                let offset = if width > 0.0 {
+                    (u - start) / width
+                } else {
+                    0.0
+                };
+                (idx as f64 + offset) / bins as f64
+            }
+        }
+    }
+}
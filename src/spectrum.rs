@@ -0,0 +1,239 @@
+use std::ops::{Add, AddAssign, Mul, MulAssign};
+
+use crate::{color::Color, vec3::Vec3};
+
+pub const LAMBDA_MIN: f64 = 380.0;
+pub const LAMBDA_MAX: f64 = 780.0;
+pub const N_SPECTRUM_SAMPLES: usize = 4;
+
+// Integral of the fitted y colour matching function over [LAMBDA_MIN, LAMBDA_MAX].
+const CIE_Y_INTEGRAL: f64 = 106.919_734_638;
+
+// Linear sRGB of a constant unit spectrum before balancing. Dividing by it maps
+// the equal energy white to (1, 1, 1), so grey albedos stay grey.
+const WHITE_BALANCE: Vec3 = Vec3 {
+    x: 1.200_536_303,
+    y: 0.949_666_413,
+    z: 0.907_828_671,
+};
+
+/// Wavelengths (in nm) carried by a single camera sample. The first one is the
+/// hero wavelength, the others are evenly rotated around the visible range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampledWavelengths {
+    lambda: [f64; N_SPECTRUM_SAMPLES],
+    pdf: [f64; N_SPECTRUM_SAMPLES],
+}
+
+impl SampledWavelengths {
+    pub fn sample_uniform(u: f64) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + u * range;
+        let delta = range / N_SPECTRUM_SAMPLES as f64;
+
+        let mut lambda = [hero; N_SPECTRUM_SAMPLES];
+        for i in 1..N_SPECTRUM_SAMPLES {
+            lambda[i] = lambda[i - 1] + delta;
+            if lambda[i] > LAMBDA_MAX {
+                lambda[i] -= range;
+            }
+        }
+
+        Self {
+            lambda,
+            pdf: [1.0 / range; N_SPECTRUM_SAMPLES],
+        }
+    }
+
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    pub fn secondary_terminated(&self) -> bool {
+        self.pdf[1..].iter().all(|&pdf| pdf == 0.0)
+    }
+
+    /// Drops every wavelength but the hero, used when a path goes through a
+    /// wavelength dependent event such as dispersive refraction.
+    pub fn terminate_secondary(&mut self) {
+        if self.secondary_terminated() {
+            return;
+        }
+        for pdf in &mut self.pdf[1..] {
+            *pdf = 0.0;
+        }
+        self.pdf[0] /= N_SPECTRUM_SAMPLES as f64;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SampledSpectrum([f64; N_SPECTRUM_SAMPLES]);
+
+impl SampledSpectrum {
+    pub fn new(value: f64) -> Self {
+        Self([value; N_SPECTRUM_SAMPLES])
+    }
+
+    /// Upsamples an RGB reflectance to the sampled wavelengths.
+    pub fn from_rgb(rgb: &Color, lambda: &SampledWavelengths) -> Self {
+        Self(lambda.lambda.map(|l| rgb_to_spectrum(rgb, l)))
+    }
+
+    pub fn max_value(&self) -> f64 {
+        self.0.iter().copied().fold(0.0, f64::max)
+    }
+
+    /// Projects the spectrum onto the CIE observer and returns linear sRGB.
+    pub fn to_rgb(&self, lambda: &SampledWavelengths) -> Vec3 {
+        let mut xyz = Vec3::default();
+        for i in 0..N_SPECTRUM_SAMPLES {
+            if lambda.pdf[i] != 0.0 {
+                xyz += cie_xyz(lambda.lambda[i]) * (self.0[i] / lambda.pdf[i]);
+            }
+        }
+        xyz /= N_SPECTRUM_SAMPLES as f64 * CIE_Y_INTEGRAL;
+
+        let rgb = Vec3::new(
+            3.240_454_2 * xyz.x - 1.537_138_5 * xyz.y - 0.498_531_4 * xyz.z,
+            -0.969_266_0 * xyz.x + 1.876_010_8 * xyz.y + 0.041_556_0 * xyz.z,
+            0.055_643_4 * xyz.x - 0.204_025_9 * xyz.y + 1.057_225_2 * xyz.z,
+        );
+        Vec3::new(
+            rgb.x / WHITE_BALANCE.x,
+            rgb.y / WHITE_BALANCE.y,
+            rgb.z / WHITE_BALANCE.z,
+        )
+    }
+}
+
+impl From<[f64; N_SPECTRUM_SAMPLES]> for SampledSpectrum {
+    fn from(value: [f64; N_SPECTRUM_SAMPLES]) -> Self {
+        Self(value)
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        self += rhs;
+        self
+    }
+}
+
+impl AddAssign for SampledSpectrum {
+    fn add_assign(&mut self, rhs: Self) {
+        for (a, b) in self.0.iter_mut().zip(rhs.0) {
+            *a += b;
+        }
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(mut self, rhs: Self) -> Self::Output {
+        self *= rhs;
+        self
+    }
+}
+
+impl MulAssign for SampledSpectrum {
+    fn mul_assign(&mut self, rhs: Self) {
+        for (a, b) in self.0.iter_mut().zip(rhs.0) {
+            *a *= b;
+        }
+    }
+}
+
+impl Mul<f64> for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(mut self, rhs: f64) -> Self::Output {
+        self *= rhs;
+        self
+    }
+}
+
+impl MulAssign<f64> for SampledSpectrum {
+    fn mul_assign(&mut self, rhs: f64) {
+        for a in &mut self.0 {
+            *a *= rhs;
+        }
+    }
+}
+
+fn gaussian(lambda: f64, mu: f64, sigma_lo: f64, sigma_hi: f64) -> f64 {
+    let sigma = if lambda < mu { sigma_lo } else { sigma_hi };
+    let t = (lambda - mu) / sigma;
+    (-0.5 * t * t).exp()
+}
+
+/// CIE 1931 colour matching functions, multi-lobe fit from Wyman, Sloan and
+/// Shirley (2013).
+pub fn cie_xyz(lambda: f64) -> Vec3 {
+    let x = 1.056 * gaussian(lambda, 599.8, 37.9, 31.0)
+        + 0.362 * gaussian(lambda, 442.0, 16.0, 26.7)
+        - 0.065 * gaussian(lambda, 501.1, 20.4, 26.2);
+    let y =
+        0.821 * gaussian(lambda, 568.8, 46.9, 40.5) + 0.286 * gaussian(lambda, 530.9, 16.3, 31.1);
+    let z =
+        1.217 * gaussian(lambda, 437.0, 11.8, 36.0) + 0.681 * gaussian(lambda, 459.0, 26.0, 13.8);
+
+    Vec3::new(x, y, z)
+}
+
+// Smits (1999) basis spectra, 10 bins over [380, 720] nm.
+const SMITS_BINS: usize = 10;
+const SMITS_MIN: f64 = 380.0;
+const SMITS_MAX: f64 = 720.0;
+const SMITS_WHITE: [f64; SMITS_BINS] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; SMITS_BINS] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; SMITS_BINS] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; SMITS_BINS] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; SMITS_BINS] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; SMITS_BINS] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; SMITS_BINS] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+fn rgb_to_spectrum(rgb: &Color, lambda: f64) -> f64 {
+    let bin = ((lambda - SMITS_MIN) / (SMITS_MAX - SMITS_MIN) * SMITS_BINS as f64)
+        .clamp(0.0, (SMITS_BINS - 1) as f64) as usize;
+    let (r, g, b) = (rgb.x, rgb.y, rgb.z);
+
+    if r <= g && r <= b {
+        let base = r * SMITS_WHITE[bin];
+        if g <= b {
+            base + (g - r) * SMITS_CYAN[bin] + (b - g) * SMITS_BLUE[bin]
+        } else {
+            base + (b - r) * SMITS_CYAN[bin] + (g - b) * SMITS_GREEN[bin]
+        }
+    } else if g <= r && g <= b {
+        let base = g * SMITS_WHITE[bin];
+        if r <= b {
+            base + (r - g) * SMITS_MAGENTA[bin] + (b - r) * SMITS_BLUE[bin]
+        } else {
+            base + (b - g) * SMITS_MAGENTA[bin] + (r - b) * SMITS_RED[bin]
+        }
+    } else {
+        let base = b * SMITS_WHITE[bin];
+        if r <= g {
+            base + (r - b) * SMITS_YELLOW[bin] + (g - r) * SMITS_GREEN[bin]
+        } else {
+            base + (g - b) * SMITS_YELLOW[bin] + (r - g) * SMITS_RED[bin]
+        }
+    }
+}
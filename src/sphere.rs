@@ -1,5 +1,22 @@
+use std::f64::consts::PI;
+
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{
+    _mm256_add_pd, _mm256_div_pd, _mm256_mul_pd, _mm256_set1_pd, _mm256_sqrt_pd, _mm256_sub_pd,
+};
+
+#[cfg(target_arch = "x86_64")]
+use crate::packet;
 use crate::{
-    aabb::AABB, hittable::{HitRecord, Hittable}, interval::Interval, ray::Ray, vec3::{Point, Vec3}
+    aabb::AABB,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    light::{Light, LightShape},
+    material::Material,
+    packet::{PACKET_WIDTH, RayPacket},
+    ray::Ray,
+    texture::{AlphaMask, SurfaceMap},
+    vec3::{Point, Vec3},
 };
 
 pub struct Sphere<'a, T> {
@@ -7,7 +24,8 @@ pub struct Sphere<'a, T> {
     radius: f64,
     material: &'a T,
     bbox: AABB,
-
+    surface_map: Option<&'a SurfaceMap>,
+    alpha_mask: Option<&'a AlphaMask>,
 }
 
 impl<'a, T> Sphere<'a, T> {
@@ -19,7 +37,9 @@ impl<'a, T> Sphere<'a, T> {
             center: Ray::new(center, Vec3::default()),
             radius,
             material,
-            bbox
+            bbox,
+            surface_map: None,
+            alpha_mask: None,
         }
     }
 
@@ -33,13 +53,72 @@ impl<'a, T> Sphere<'a, T> {
             center: Ray::new(center1, center2 - center1),
             radius,
             material,
-            bbox
+            bbox,
+            surface_map: None,
+            alpha_mask: None,
         }
     }
+
+    /// Perturbs the shading normal with a normal or bump map.
+    pub fn with_surface_map(mut self, surface_map: &'a SurfaceMap) -> Self {
+        self.surface_map = Some(surface_map);
+        self
+    }
+
+    /// Cuts out the parts of the sphere where the mask is transparent.
+    pub fn with_alpha_mask(mut self, alpha_mask: &'a AlphaMask) -> Self {
+        self.alpha_mask = Some(alpha_mask);
+        self
+    }
 }
 
-impl<'a, T> Hittable<T> for Sphere<'a, T> {
-    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<T>> {
+impl<T: Material> Sphere<'_, T> {
+    /// The sphere as the light of the object `object_id`, when its material
+    /// emits the same radiance all over it. Moving spheres are taken where
+    /// they start.
+    pub fn light(&self, object_id: u32) -> Option<Light> {
+        let radiance = self.material.uniform_emission()?;
+        let shape = LightShape::Sphere {
+            center: self.center.origin,
+            radius: self.radius,
+        };
+        Some(Light::new(object_id, shape, radiance))
+    }
+}
+
+impl<T> Sphere<'_, T> {
+    fn get_sphere_uv(p: &Point) -> (f64, f64) {
+        // p: a given point on the sphere of radius one, centered at the origin.
+        // u: returned value [0,1] of angle around the Y axis from X=-1.
+        // v: returned value [0,1] of angle from Y=-1 to Y=+1.
+        let theta = (-p.y).acos();
+        let phi = (-p.z).atan2(p.x) + PI;
+
+        (phi / (2.0 * PI), theta / PI)
+    }
+
+    fn get_sphere_tangents(&self, n: &Vec3) -> (Vec3, Vec3) {
+        // Derivatives of the parametrization above, scaled by the radius. At
+        // the poles dp/du vanishes so an arbitrary tangent frame is used.
+        let sin_theta = (n.x * n.x + n.z * n.z).sqrt();
+        if sin_theta < 1e-9 {
+            return (
+                Vec3::new(2.0 * PI * self.radius, 0.0, 0.0),
+                Vec3::new(0.0, 0.0, -PI * self.radius * n.y.signum()),
+            );
+        }
+
+        let dpdu = 2.0 * PI * self.radius * Vec3::new(n.z, 0.0, -n.x);
+        let dpdv =
+            PI * self.radius * Vec3::new(-n.y * n.x / sin_theta, sin_theta, -n.y * n.z / sin_theta);
+        (dpdu, dpdv)
+    }
+}
+
+impl<T> Sphere<'_, T> {
+    /// Center of the sphere at the time of the ray, and the ray parameters
+    /// where they meet, nearest first.
+    fn roots(&self, ray: &Ray) -> Option<(Point, [f64; 2])> {
         let current_center = self.center.at(ray.time);
         let oc = current_center - ray.origin;
         let a = ray.dir.length_squared();
@@ -52,18 +131,137 @@ impl<'a, T> Hittable<T> for Sphere<'a, T> {
         }
 
         let sqrt_d = discriminant.sqrt();
+        Some((current_center, [(h - sqrt_d) / a, (h + sqrt_d) / a]))
+    }
 
-        let mut root = (h - sqrt_d) / a;
-        if !ray_t.contains(root) {
-            root = (h + sqrt_d) / a;
-            if !ray_t.contains(root) {
+    /// `roots` for every ray of `packet`.
+    fn roots_packet(&self, packet: &RayPacket) -> [Option<(Point, [f64; 2])>; PACKET_WIDTH] {
+        #[cfg(target_arch = "x86_64")]
+        if packet::has_avx() {
+            // SAFETY: the CPU was just found to support AVX.
+            return unsafe { self.roots_packet_avx(packet) };
+        }
+        std::array::from_fn(|lane| self.roots(&packet.rays[lane]))
+    }
+
+    /// Same operations as `roots` in the same order, so that every lane gets
+    /// exactly the roots its ray would.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    #[target_feature(enable = "avx")]
+    fn roots_packet_avx(&self, packet: &RayPacket) -> [Option<(Point, [f64; 2])>; PACKET_WIDTH] {
+        let time = packet::load(&packet.time);
+        let center = [
+            (self.center.origin.x, self.center.dir.x),
+            (self.center.origin.y, self.center.dir.y),
+            (self.center.origin.z, self.center.dir.z),
+        ]
+        .map(|(origin, dir)| {
+            _mm256_add_pd(
+                _mm256_set1_pd(origin),
+                _mm256_mul_pd(_mm256_set1_pd(dir), time),
+            )
+        });
+        let dir = packet.dir.each_ref().map(|d| packet::load(d));
+        let oc: [_; 3] =
+            std::array::from_fn(|i| _mm256_sub_pd(center[i], packet::load(&packet.origin[i])));
+        let dot = |a: &[_; 3], b: &[_; 3]| {
+            _mm256_add_pd(
+                _mm256_add_pd(_mm256_mul_pd(a[0], b[0]), _mm256_mul_pd(a[1], b[1])),
+                _mm256_mul_pd(a[2], b[2]),
+            )
+        };
+        let a = dot(&dir, &dir);
+        let h = dot(&dir, &oc);
+        let c = _mm256_sub_pd(dot(&oc, &oc), _mm256_set1_pd(self.radius * self.radius));
+
+        let discriminant = _mm256_sub_pd(_mm256_mul_pd(h, h), _mm256_mul_pd(a, c));
+        let sqrt_d = _mm256_sqrt_pd(discriminant);
+        let near = packet::store(_mm256_div_pd(_mm256_sub_pd(h, sqrt_d), a));
+        let far = packet::store(_mm256_div_pd(_mm256_add_pd(h, sqrt_d), a));
+        let discriminant = packet::store(discriminant);
+        let center = center.map(|axis| packet::store(axis));
+
+        std::array::from_fn(|lane| {
+            if discriminant[lane] < 0.0 {
                 return None;
             }
+            let current_center = Point::new(center[0][lane], center[1][lane], center[2][lane]);
+            Some((current_center, [near[lane], far[lane]]))
+        })
+    }
+
+    /// Nearest hit within `ray_t` among the `roots` of `ray`.
+    fn hit_roots(
+        &self,
+        ray: &Ray,
+        ray_t: Interval,
+        (current_center, roots): (Point, [f64; 2]),
+    ) -> Option<HitRecord<'_, T>> {
+        // Try the nearest root first, the far one is reached when the near one
+        // is out of range or cut out by the alpha mask.
+        for root in roots {
+            if !ray_t.contains(root) {
+                continue;
+            }
+
+            let p = ray.at(root);
+            let outward_normal = (p - current_center) / self.radius;
+            let (u, v) = Self::get_sphere_uv(&outward_normal);
+            if self.is_cut_out(&p, (u, v)) {
+                continue;
+            }
+
+            let mut rec = HitRecord::new(ray, p, root, outward_normal, (u, v), self.material);
+            (rec.dpdu, rec.dpdv) = self.get_sphere_tangents(&outward_normal);
+
+            if let Some(surface_map) = self.surface_map {
+                let normal = surface_map.shading_normal(&rec, &outward_normal);
+                rec.set_shading_normal(normal);
+            }
+            return Some(rec);
         }
 
-        let p = ray.at(root);
-        let outward_normal = (p - current_center) / self.radius;
-        Some(HitRecord::new(ray, p, root, outward_normal, self.material))
+        None
+    }
+
+    fn is_cut_out(&self, p: &Point, (u, v): (f64, f64)) -> bool {
+        self.alpha_mask.is_some_and(|mask| !mask.is_opaque(u, v, p))
+    }
+}
+
+impl<'a, T> Hittable<T> for Sphere<'a, T> {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_, T>> {
+        self.hit_roots(ray, ray_t, self.roots(ray)?)
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_t: &[Interval; PACKET_WIDTH],
+    ) -> [Option<HitRecord<'_, T>>; PACKET_WIDTH] {
+        let roots = self.roots_packet(packet);
+        std::array::from_fn(|lane| self.hit_roots(&packet.rays[lane], ray_t[lane], roots[lane]?))
+    }
+
+    fn occluded(&self, ray: &Ray, ray_t: Interval) -> bool {
+        let Some((current_center, roots)) = self.roots(ray) else {
+            return false;
+        };
+        roots
+            .into_iter()
+            .filter(|&root| ray_t.contains(root))
+            .any(|root| {
+                // Surface coordinates are only needed to look up the alpha mask.
+                if self.alpha_mask.is_none() {
+                    return true;
+                }
+                let p = ray.at(root);
+                !self.is_cut_out(
+                    &p,
+                    Self::get_sphere_uv(&((p - current_center) / self.radius)),
+                )
+            })
     }
 
     fn bounding_box(&self) -> AABB {
@@ -0,0 +1,296 @@
+use std::{fs, io, path::Path, sync::Arc};
+
+use crate::{
+    color::Color,
+    hittable::HitRecord,
+    interval::Interval,
+    vec3::{Point, Vec3},
+};
+
+pub trait Texture {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Color;
+}
+
+#[derive(Debug, Clone)]
+pub enum TextureKind {
+    Solid(Color),
+    Checker(Checker),
+    Image(Arc<ImageTexture>),
+}
+
+impl From<Color> for TextureKind {
+    fn from(value: Color) -> Self {
+        TextureKind::Solid(value)
+    }
+}
+
+impl Texture for TextureKind {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Color {
+        match self {
+            TextureKind::Solid(color) => *color,
+            TextureKind::Checker(tex) => tex.value(u, v, p),
+            TextureKind::Image(tex) => tex.value(u, v, p),
+        }
+    }
+}
+
+/// Scalar material parameter, either constant or read from one channel of a
+/// texture (glTF packs roughness in green and metallic in blue).
+#[derive(Debug, Clone)]
+pub enum ScalarTexture {
+    Constant(f64),
+    Red(TextureKind),
+    Green(TextureKind),
+    Blue(TextureKind),
+}
+
+impl ScalarTexture {
+    pub fn value(&self, u: f64, v: f64, p: &Point) -> f64 {
+        match self {
+            ScalarTexture::Constant(value) => *value,
+            ScalarTexture::Red(tex) => tex.value(u, v, p).x,
+            ScalarTexture::Green(tex) => tex.value(u, v, p).y,
+            ScalarTexture::Blue(tex) => tex.value(u, v, p).z,
+        }
+    }
+}
+
+impl From<f64> for ScalarTexture {
+    fn from(value: f64) -> Self {
+        ScalarTexture::Constant(value)
+    }
+}
+
+/// Solid 3D checker pattern.
+#[derive(Debug, Clone)]
+pub struct Checker {
+    inv_scale: f64,
+    even: Color,
+    odd: Color,
+}
+
+impl Checker {
+    pub fn new(scale: f64, even: Color, odd: Color) -> Self {
+        Self {
+            inv_scale: 1.0 / scale,
+            even,
+            odd,
+        }
+    }
+}
+
+impl Texture for Checker {
+    fn value(&self, _u: f64, _v: f64, p: &Point) -> Color {
+        let x = (self.inv_scale * p.x).floor() as i64;
+        let y = (self.inv_scale * p.y).floor() as i64;
+        let z = (self.inv_scale * p.z).floor() as i64;
+
+        if (x + y + z).rem_euclid(2) == 0 {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    // Linear colors, row major starting at the top left
+    pixels: Vec<Color>,
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), width * height, "image size mismatch");
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixel(&self, i: usize, j: usize) -> Color {
+        self.pixels[j * self.width + i]
+    }
+
+    /// Loads a binary (P6) or plain (P3) PPM. Byte values are gamma encoded
+    /// the same way `Color` writes them.
+    pub fn from_ppm(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read_ppm(path, true)
+    }
+
+    /// Loads a PPM holding data rather than colors, such as normal or height
+    /// maps, without decoding gamma.
+    pub fn from_ppm_linear(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::read_ppm(path, false)
+    }
+
+    fn read_ppm(path: impl AsRef<Path>, gamma_encoded: bool) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+
+        // Header tokens, skipping comments
+        let mut tokens = Vec::new();
+        let mut pos = 0;
+        while tokens.len() < 4 {
+            while pos < data.len() && data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if pos < data.len() && data[pos] == b'#' {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+                continue;
+            }
+            let start = pos;
+            while pos < data.len() && !data[pos].is_ascii_whitespace() {
+                pos += 1;
+            }
+            if start == pos {
+                return Err(invalid("truncated PPM header"));
+            }
+            tokens.push(String::from_utf8_lossy(&data[start..pos]).into_owned());
+        }
+
+        let parse = |s: &str| {
+            s.parse::<usize>()
+                .map_err(|_| invalid("invalid PPM header"))
+        };
+        let width = parse(&tokens[1])?;
+        let height = parse(&tokens[2])?;
+        let max_value = parse(&tokens[3])? as f64;
+
+        let samples: Vec<f64> = match tokens[0].as_str() {
+            "P6" => data
+                .get(pos + 1..)
+                .ok_or_else(|| invalid("truncated PPM data"))?
+                .iter()
+                .map(|&b| b as f64)
+                .collect(),
+            "P3" => String::from_utf8_lossy(&data[pos..])
+                .split_ascii_whitespace()
+                .map(|s| s.parse::<f64>().map_err(|_| invalid("invalid PPM data")))
+                .collect::<io::Result<_>>()?,
+            _ => return Err(invalid("unsupported PPM format")),
+        };
+        if samples.len() < width * height * 3 {
+            return Err(invalid("truncated PPM data"));
+        }
+
+        let to_linear = |s: f64| {
+            let value = s / max_value;
+            if gamma_encoded { value * value } else { value }
+        };
+        let pixels = samples
+            .chunks_exact(3)
+            .take(width * height)
+            .map(|c| Color::new(to_linear(c[0]), to_linear(c[1]), to_linear(c[2])))
+            .collect();
+
+        Ok(Self::new(width, height, pixels))
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point) -> Color {
+        if self.height == 0 {
+            return Color::new(0.0, 1.0, 1.0);
+        }
+
+        // Clamp input texture coordinates to [0,1] x [1,0]
+        let unit = Interval::new(0.0, 1.0);
+        let u = unit.clamp(u);
+        let v = 1.0 - unit.clamp(v);
+
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.pixels[j * self.width + i]
+    }
+}
+
+/// Cutout mask, intersections where the opacity falls below the threshold are
+/// ignored and the ray carries on through the surface.
+#[derive(Debug, Clone)]
+pub struct AlphaMask {
+    opacity: ScalarTexture,
+    threshold: f64,
+}
+
+impl AlphaMask {
+    pub fn new(opacity: impl Into<ScalarTexture>, threshold: f64) -> Self {
+        Self {
+            opacity: opacity.into(),
+            threshold,
+        }
+    }
+
+    pub fn is_opaque(&self, u: f64, v: f64, p: &Point) -> bool {
+        self.opacity.value(u, v, p) >= self.threshold
+    }
+}
+
+/// Perturbation of the shading normal of a surface.
+#[derive(Debug, Clone)]
+pub enum SurfaceMap {
+    /// Tangent space normal map, with RGB in [0,1] encoding XYZ in [-1,1].
+    Normal(TextureKind),
+    /// Height field displacing the surface along its normal.
+    Bump { height: ScalarTexture, scale: f64 },
+}
+
+impl SurfaceMap {
+    // Step in texture space for the bump map finite differences
+    const BUMP_DELTA: f64 = 1e-3;
+
+    /// Outward shading normal for a hit with `outward_normal` as geometric
+    /// normal and tangents already filled in.
+    pub fn shading_normal<T>(&self, rec: &HitRecord<T>, outward_normal: &Vec3) -> Vec3 {
+        let (u, v, p) = (rec.u, rec.v, &rec.p);
+        match self {
+            SurfaceMap::Normal(tex) => {
+                let c = tex.value(u, v, p);
+                let local = Vec3::new(2.0 * c.x - 1.0, 2.0 * c.y - 1.0, 2.0 * c.z - 1.0);
+
+                // Gram-Schmidt the tangent against the normal
+                let n = *outward_normal;
+                let t = (rec.dpdu - n * n.dot(&rec.dpdu)).unit_vector();
+                let mut b = n.cross(&t);
+                if b.dot(&rec.dpdv) < 0.0 {
+                    b = -b;
+                }
+
+                let perturbed = (local.x * t + local.y * b + local.z * n).unit_vector();
+                if perturbed.near_zero() { n } else { perturbed }
+            }
+            SurfaceMap::Bump { height, scale } => {
+                let d = Self::BUMP_DELTA;
+                let displace = height.value(u, v, p);
+                let displace_u = height.value(u + d, v, p);
+                let displace_v = height.value(u, v + d, p);
+
+                let n = *outward_normal;
+                let dpdu = rec.dpdu + n * (scale * (displace_u - displace) / d);
+                let dpdv = rec.dpdv + n * (scale * (displace_v - displace) / d);
+
+                let perturbed = dpdu.cross(&dpdv).unit_vector();
+                if perturbed.near_zero() {
+                    n
+                } else if perturbed.dot(&n) < 0.0 {
+                    -perturbed
+                } else {
+                    perturbed
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use crate::{
+    aabb::AABB,
+    animation::Track,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    ray::Ray,
+    vec3::{Point, Vec3},
+};
+
+/// Animated placement of an object: scaled, then rotated around X, Y and Z
+/// (Euler angles in degrees), then translated.
+#[derive(Debug, Clone)]
+pub struct Transform {
+    translation: Track<Vec3>,
+    rotation: Track<Vec3>,
+    scale: Track<Vec3>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: Track::constant(Vec3::default()),
+            rotation: Track::constant(Vec3::default()),
+            scale: Track::constant(Vec3::new(1.0, 1.0, 1.0)),
+        }
+    }
+}
+
+impl Transform {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn translation(mut self, track: Track<Vec3>) -> Self {
+        self.translation = track;
+        self
+    }
+
+    pub fn rotation(mut self, track: Track<Vec3>) -> Self {
+        self.rotation = track;
+        self
+    }
+
+    pub fn scale(mut self, track: Track<Vec3>) -> Self {
+        self.scale = track;
+        self
+    }
+
+    fn at(&self, time: f64) -> Pose {
+        let angles = self.rotation.value_at(time);
+        let (sx, cx) = angles.x.to_radians().sin_cos();
+        let (sy, cy) = angles.y.to_radians().sin_cos();
+        let (sz, cz) = angles.z.to_radians().sin_cos();
+
+        // Rz * Ry * Rx, stored as rows
+        let rotation = [
+            Vec3::new(cz * cy, cz * sy * sx - sz * cx, cz * sy * cx + sz * sx),
+            Vec3::new(sz * cy, sz * sy * sx + cz * cx, sz * sy * cx - cz * sx),
+            Vec3::new(-sy, cy * sx, cy * cx),
+        ];
+
+        Pose {
+            translation: self.translation.value_at(time),
+            rotation,
+            scale: self.scale.value_at(time),
+        }
+    }
+
+    fn key_times(&self) -> impl Iterator<Item = f64> + '_ {
+        self.translation
+            .times()
+            .chain(self.rotation.times())
+            .chain(self.scale.times())
+    }
+}
+
+struct Pose {
+    translation: Vec3,
+    rotation: [Vec3; 3],
+    scale: Vec3,
+}
+
+impl Pose {
+    fn rotate(&self, v: &Vec3) -> Vec3 {
+        Vec3::new(
+            self.rotation[0].dot(v),
+            self.rotation[1].dot(v),
+            self.rotation[2].dot(v),
+        )
+    }
+
+    fn rotate_inverse(&self, v: &Vec3) -> Vec3 {
+        self.rotation[0] * v.x + self.rotation[1] * v.y + self.rotation[2] * v.z
+    }
+
+    fn to_world(&self, p: &Point) -> Point {
+        self.rotate(&(p * self.scale)) + self.translation
+    }
+
+    fn dir_to_world(&self, v: &Vec3) -> Vec3 {
+        self.rotate(&(v * self.scale))
+    }
+
+    fn normal_to_world(&self, n: &Vec3) -> Vec3 {
+        // Inverse transpose of rotation * scale
+        let n = Vec3::new(n.x / self.scale.x, n.y / self.scale.y, n.z / self.scale.z);
+        self.rotate(&n).unit_vector()
+    }
+
+    fn to_object(&self, p: &Point) -> Point {
+        let p = self.rotate_inverse(&(p - self.translation));
+        Vec3::new(p.x / self.scale.x, p.y / self.scale.y, p.z / self.scale.z)
+    }
+
+    fn dir_to_object(&self, v: &Vec3) -> Vec3 {
+        let v = self.rotate_inverse(v);
+        Vec3::new(v.x / self.scale.x, v.y / self.scale.y, v.z / self.scale.z)
+    }
+}
+
+/// Object placed by an animated `Transform`, evaluated at each ray's time.
+pub struct Transformed<H> {
+    object: H,
+    transform: Transform,
+    bbox: AABB,
+}
+
+impl<H> Transformed<H> {
+    // Poses sampled between keyframes when bounding the motion
+    const BOUNDS_SAMPLES: usize = 16;
+
+    /// Wraps `object`, bounding its motion over the `time` interval, usually
+    /// the shutter interval of the frame being rendered.
+    pub fn new<M>(object: H, transform: Transform, time: Interval) -> Self
+    where
+        H: Hittable<M>,
+    {
+        let object_box = object.bounding_box();
+        let mut times: Vec<f64> = (0..=Self::BOUNDS_SAMPLES)
+            .map(|i| time.min + time.size() * i as f64 / Self::BOUNDS_SAMPLES as f64)
+            .chain(transform.key_times().filter(|t| time.contains(*t)))
+            .collect();
+        times.dedup();
+
+        let mut bbox = AABB::EMPTY;
+        for t in times {
+            let pose = transform.at(t);
+            for corner in 0..8 {
+                let pick = |axis: usize| {
+                    let interval = object_box.axis(axis);
+                    if corner & (1 << axis) == 0 {
+                        interval.min
+                    } else {
+                        interval.max
+                    }
+                };
+                let p = Point::new(pick(0), pick(1), pick(2));
+                let p = pose.to_world(&p);
+                bbox = AABB::new_from_boxes(&bbox, &AABB::new_from_points(p, p));
+            }
+        }
+        // Rotations may bulge out between the sampled poses.
+        bbox.pad(0.01 * bbox.diagonal().length());
+
+        Self {
+            object,
+            transform,
+            bbox,
+        }
+    }
+}
+
+impl<M, H: Hittable<M>> Hittable<M> for Transformed<H> {
+    fn hit(&self, ray: &Ray, ray_t: Interval) -> Option<HitRecord<'_, M>> {
+        let pose = self.transform.at(ray.time);
+
+        // The mapping is affine, so the ray parameter is the same in both spaces.
+        let object_ray = ray.spawn(pose.to_object(&ray.origin), pose.dir_to_object(&ray.dir));
+        let mut rec = self.object.hit(&object_ray, ray_t)?;
+
+        rec.p = pose.to_world(&rec.p);
+        rec.normal = pose.normal_to_world(&rec.normal);
+        rec.dpdu = pose.dir_to_world(&rec.dpdu);
+        rec.dpdv = pose.dir_to_world(&rec.dpdv);
+        Some(rec)
+    }
+
+    fn occluded(&self, ray: &Ray, ray_t: Interval) -> bool {
+        let pose = self.transform.at(ray.time);
+        let object_ray = ray.spawn(pose.to_object(&ray.origin), pose.dir_to_object(&ray.dir));
+        self.object.occluded(&object_ray, ray_t)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bbox.clone()
+    }
+
+    fn traversal_steps(&self, ray: &Ray, ray_t: Interval) -> u32 {
+        let pose = self.transform.at(ray.time);
+        let object_ray = ray.spawn(pose.to_object(&ray.origin), pose.dir_to_object(&ray.dir));
+        self.object.traversal_steps(&object_ray, ray_t)
+    }
+}
@@ -67,6 +67,42 @@ impl Vec3 {
         }
     }
 
+    /// Cosine weighted direction around +Z from a uniform point of [0,1)^2.
+    pub fn sample_cosine_direction((r1, r2): (f64, f64)) -> Vec3 {
+        let phi = 2.0 * std::f64::consts::PI * r1;
+        let x = phi.cos() * r2.sqrt();
+        let y = phi.sin() * r2.sqrt();
+        let z = (1.0 - r2).sqrt();
+
+        Vec3::new(x, y, z)
+    }
+
+    /// Uniform direction on the unit sphere from a uniform point of [0,1)^2.
+    pub fn sample_unit((u1, u2): (f64, f64)) -> Vec3 {
+        let z = 1.0 - 2.0 * u1;
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * u2;
+
+        Vec3::new(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    /// Uniform point of the unit disk from a uniform point of [0,1)^2, with
+    /// Shirley and Chiu's concentric mapping which keeps strata compact.
+    pub fn sample_unit_disk((u1, u2): (f64, f64)) -> Vec3 {
+        let (a, b) = (2.0 * u1 - 1.0, 2.0 * u2 - 1.0);
+        if a == 0.0 && b == 0.0 {
+            return Vec3::default();
+        }
+
+        let quarter_pi = std::f64::consts::FRAC_PI_4;
+        let (r, theta) = if a.abs() > b.abs() {
+            (a, quarter_pi * (b / a))
+        } else {
+            (b, 2.0 * quarter_pi - quarter_pi * (a / b))
+        };
+        Vec3::new(r * theta.cos(), r * theta.sin(), 0.0)
+    }
+
     pub fn length(&self) -> f64 {
         self.length_squared().sqrt()
     }
@@ -0,0 +1,562 @@
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::{__m256d, _mm256_add_pd, _mm256_mul_pd, _mm256_set1_pd};
+
+#[cfg(target_arch = "x86_64")]
+use crate::{aabb, packet};
+use crate::{
+    aabb::AABB,
+    bvh_node::BVHNode,
+    hittable::{HitRecord, Hittable},
+    interval::Interval,
+    packet::{PACKET_WIDTH, RayPacket},
+    ray::Ray,
+};
+
+/// Children of every node of a `WideBVH`, as many as one AVX register holds
+/// the bounds of along an axis.
+pub const BVH_WIDTH: usize = 4;
+
+// Largest quantized bound, the grid of a node spanning it along every axis
+const GRID_STEPS: f64 = u8::MAX as f64;
+
+#[derive(Debug, Clone, Copy)]
+enum WideChild {
+    Empty,
+    // Index in the node list
+    Node(u32),
+    // Index in the object list
+    Leaf(u32),
+}
+
+/// Node of a `WideBVH`, with the bounds of its children rounded outwards to
+/// a grid of 255 steps along every axis of the box around them.
+#[derive(Debug, Clone)]
+struct WideNode {
+    origin: [f64; 3],
+    // Per axis, the size of a grid step, a power of two
+    scale: [f64; 3],
+    // Per axis, the bounds of every child in grid steps from `origin`
+    min: [[u8; BVH_WIDTH]; 3],
+    max: [[u8; BVH_WIDTH]; 3],
+    children: [WideChild; BVH_WIDTH],
+}
+
+impl WideNode {
+    /// Node with no children yet, whose grid covers `boxes`.
+    fn new(boxes: &[AABB]) -> Self {
+        let bounds = boxes
+            .iter()
+            .fold(AABB::EMPTY, |acc, bbox| AABB::new_from_boxes(&acc, bbox));
+        let mut node = Self {
+            origin: [0.0; 3],
+            scale: [0.0; 3],
+            min: [[u8::MAX; BVH_WIDTH]; 3],
+            max: [[0; BVH_WIDTH]; 3],
+            children: [WideChild::Empty; BVH_WIDTH],
+        };
+
+        for i in 0..3 {
+            let origin = bounds.axis(i).min;
+            let extent = bounds.axis(i).max - origin;
+            // Powers of two keep grid positions exact, and the step only
+            // grows when rounding left the far side out.
+            let mut scale = 2f64
+                .powi((extent / GRID_STEPS).log2().ceil() as i32)
+                .max(f64::MIN_POSITIVE);
+            while origin + GRID_STEPS * scale < bounds.axis(i).max {
+                scale *= 2.0;
+            }
+            node.origin[i] = origin;
+            node.scale[i] = scale;
+
+            // Rounded outwards, checking with the same arithmetic as the
+            // tests so that child boxes never shrink.
+            for (child, bbox) in boxes.iter().enumerate() {
+                let axis = bbox.axis(i);
+                let mut min = ((axis.min - origin) / scale).floor().clamp(0.0, GRID_STEPS);
+                while min > 0.0 && origin + min * scale > axis.min {
+                    min -= 1.0;
+                }
+                let mut max = ((axis.max - origin) / scale).ceil().clamp(0.0, GRID_STEPS);
+                while max < GRID_STEPS && origin + max * scale < axis.max {
+                    max += 1.0;
+                }
+                node.min[i][child] = min as u8;
+                node.max[i][child] = max as u8;
+            }
+        }
+        node
+    }
+
+    /// Box of the child at `index`, as stored.
+    fn child_box(&self, index: usize) -> AABB {
+        let side = |i: usize, steps: &[[u8; BVH_WIDTH]; 3]| {
+            self.origin[i] + steps[i][index] as f64 * self.scale[i]
+        };
+        AABB::new(
+            Interval::new(side(0, &self.min), side(0, &self.max)),
+            Interval::new(side(1, &self.min), side(1, &self.max)),
+            Interval::new(side(2, &self.min), side(2, &self.max)),
+        )
+    }
+
+    /// Which children have a box `ray` goes through within `ray_t`.
+    fn hit_children(&self, ray: &Ray, ray_t: Interval) -> [bool; BVH_WIDTH] {
+        self.occupied(self.hit_boxes(ray, ray_t))
+    }
+
+    /// Per child, which rays of `packet` go through its box within their
+    /// own interval.
+    fn hit_children_packet(
+        &self,
+        packet: &RayPacket,
+        ray_t: &[Interval; PACKET_WIDTH],
+    ) -> [[bool; PACKET_WIDTH]; BVH_WIDTH] {
+        let lanes = self
+            .hit_boxes_packet(packet, ray_t)
+            .map(|hits| self.occupied(hits));
+        std::array::from_fn(|index| lanes.map(|hits| hits[index]))
+    }
+
+    /// `hits` without the empty slots, whose inverted boxes slab tests
+    /// don't reject.
+    fn occupied(&self, hits: [bool; BVH_WIDTH]) -> [bool; BVH_WIDTH] {
+        std::array::from_fn(|index| {
+            hits[index] && !matches!(self.children[index], WideChild::Empty)
+        })
+    }
+
+    fn hit_boxes(&self, ray: &Ray, ray_t: Interval) -> [bool; BVH_WIDTH] {
+        #[cfg(target_arch = "x86_64")]
+        if packet::has_avx() {
+            // SAFETY: the CPU was just found to support AVX.
+            return unsafe { self.hit_boxes_avx(ray, ray_t) };
+        }
+        std::array::from_fn(|index| self.child_box(index).hit(ray, ray_t))
+    }
+
+    /// Per ray of `packet`, `hit_boxes` within its own interval.
+    fn hit_boxes_packet(
+        &self,
+        packet: &RayPacket,
+        ray_t: &[Interval; PACKET_WIDTH],
+    ) -> [[bool; BVH_WIDTH]; PACKET_WIDTH] {
+        #[cfg(target_arch = "x86_64")]
+        if packet::has_avx() {
+            // SAFETY: the CPU was just found to support AVX.
+            return unsafe { self.hit_boxes_packet_avx(packet, ray_t) };
+        }
+        std::array::from_fn(|lane| self.hit_boxes(&packet.rays[lane], ray_t[lane]))
+    }
+
+    /// Lanes of the same boxes as `child_box`, per axis.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    #[target_feature(enable = "avx")]
+    fn sides_avx(&self) -> ([__m256d; 3], [__m256d; 3]) {
+        let side = |steps: &[[u8; BVH_WIDTH]; 3]| {
+            [0, 1, 2].map(|i| {
+                let steps = packet::load(&steps[i].map(f64::from));
+                _mm256_add_pd(
+                    _mm256_set1_pd(self.origin[i]),
+                    _mm256_mul_pd(steps, _mm256_set1_pd(self.scale[i])),
+                )
+            })
+        };
+        (side(&self.min), side(&self.max))
+    }
+
+    /// Same boxes as `child_box`, tested all at once.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    #[target_feature(enable = "avx")]
+    fn hit_boxes_avx(&self, ray: &Ray, ray_t: Interval) -> [bool; BVH_WIDTH] {
+        let origin = [ray.origin.x, ray.origin.y, ray.origin.z];
+        let dir = [ray.dir.x, ray.dir.y, ray.dir.z];
+        aabb::hit_lanes(
+            self.sides_avx(),
+            (
+                origin.map(|o| _mm256_set1_pd(o)),
+                dir.map(|d| _mm256_set1_pd(1.0 / d)),
+            ),
+            (_mm256_set1_pd(ray_t.min), _mm256_set1_pd(ray_t.max)),
+        )
+    }
+
+    /// `hit_boxes_avx` for every ray of `packet`, the boxes being loaded once
+    /// for all of them.
+    #[cfg(target_arch = "x86_64")]
+    #[inline]
+    #[target_feature(enable = "avx")]
+    fn hit_boxes_packet_avx(
+        &self,
+        packet: &RayPacket,
+        ray_t: &[Interval; PACKET_WIDTH],
+    ) -> [[bool; BVH_WIDTH]; PACKET_WIDTH] {
+        let sides = self.sides_avx();
+        std::array::from_fn(|lane| {
+            aabb::hit_lanes(
+                sides,
+                (
+                    packet.origin.map(|axis| _mm256_set1_pd(axis[lane])),
+                    packet.inv_dir.map(|axis| _mm256_set1_pd(axis[lane])),
+                ),
+                (
+                    _mm256_set1_pd(ray_t[lane].min),
+                    _mm256_set1_pd(ray_t[lane].max),
+                ),
+            )
+        })
+    }
+}
+
+/// Bounding volume hierarchy whose nodes have up to `BVH_WIDTH` children,
+/// collapsed from a binary `BVHNode`. Its nodes take less memory and one
+/// test goes through all the boxes of a node, which pays off on large
+/// scenes. Hits are the same as with the binary tree.
+///
+/// Nodes are four wide only, the width of an AVX register of `f64`; the
+/// eight-wide nodes AVX-512 would allow aren't built.
+pub struct WideBVH<T> {
+    nodes: Vec<WideNode>,
+    // Objects along with their index in the list the tree was built from
+    objects: Vec<(u32, T)>,
+    root: WideChild,
+    bbox: AABB,
+}
+
+impl<T> WideBVH<T> {
+    pub fn new_from_hittable_list<M>(objects: Vec<T>) -> Self
+    where
+        T: Hittable<M>,
+    {
+        Self::from_bvh(BVHNode::new_from_hittable_list(objects))
+    }
+
+    /// Collapses `bvh`, every node taking in the children of its largest
+    /// branches until it has `BVH_WIDTH` of them.
+    pub fn from_bvh<M>(bvh: BVHNode<T>) -> Self
+    where
+        T: Hittable<M>,
+    {
+        let mut tree = Self {
+            nodes: Vec::new(),
+            objects: Vec::new(),
+            root: WideChild::Empty,
+            bbox: bvh.bounding_box(),
+        };
+        tree.root = tree.collapse(bvh);
+        tree
+    }
+
+    fn collapse<M>(&mut self, bvh: BVHNode<T>) -> WideChild
+    where
+        T: Hittable<M>,
+    {
+        let (left, right) = match bvh {
+            BVHNode::Leaf(id, object) => {
+                self.objects.push((id, object));
+                return WideChild::Leaf(self.objects.len() as u32 - 1);
+            }
+            BVHNode::Branch { left, right, .. } => (left, right),
+        };
+
+        // Branches are opened in place, which keeps the objects in the order
+        // the binary tree tests them in.
+        let mut children = vec![*left, *right];
+        while children.len() < BVH_WIDTH {
+            let largest = children
+                .iter()
+                .enumerate()
+                .filter(|(_, child)| matches!(child, BVHNode::Branch { .. }))
+                .max_by(|(_, a), (_, b)| {
+                    half_area(&a.bounding_box()).total_cmp(&half_area(&b.bounding_box()))
+                });
+            let Some((index, _)) = largest else {
+                break;
+            };
+            let BVHNode::Branch { left, right, .. } = children.remove(index) else {
+                unreachable!("only branches are opened");
+            };
+            children.insert(index, *right);
+            children.insert(index, *left);
+        }
+
+        // Parents come before their children in the node list.
+        let boxes: Vec<AABB> = children.iter().map(|child| child.bounding_box()).collect();
+        let index = self.nodes.len();
+        self.nodes.push(WideNode::new(&boxes));
+        for (slot, child) in children.into_iter().enumerate() {
+            self.nodes[index].children[slot] = self.collapse(child);
+        }
+        WideChild::Node(index as u32)
+    }
+
+    /// Narrows `ray_t` down to every hit found under `child`, keeping the
+    /// closest one in `hit`.
+    fn hit_child<'a, M>(
+        &'a self,
+        child: WideChild,
+        ray: &Ray,
+        ray_t: &mut Interval,
+        hit: &mut Option<HitRecord<'a, M>>,
+    ) where
+        T: Hittable<M>,
+    {
+        match child {
+            WideChild::Empty => {}
+            WideChild::Leaf(index) => {
+                let (id, object) = &self.objects[index as usize];
+                if let Some(mut rec) = object.hit(ray, *ray_t) {
+                    rec.object_id = *id;
+                    ray_t.max = rec.t;
+                    *hit = Some(rec);
+                }
+            }
+            WideChild::Node(index) => {
+                let node = &self.nodes[index as usize];
+                let inside = node.hit_children(ray, *ray_t);
+                for (child, inside) in node.children.into_iter().zip(inside) {
+                    if inside {
+                        self.hit_child(child, ray, ray_t, hit);
+                    }
+                }
+            }
+        }
+    }
+
+    /// `hit_child` for every ray of `packet`. The whole packet goes down
+    /// into a child while any of its rays hits the child's box, as in
+    /// `BVHNode`, the others being given empty intervals until it comes back
+    /// up.
+    fn hit_child_packet<'a, M>(
+        &'a self,
+        child: WideChild,
+        packet: &RayPacket,
+        ray_t: &mut [Interval; PACKET_WIDTH],
+        hits: &mut [Option<HitRecord<'a, M>>; PACKET_WIDTH],
+    ) where
+        T: Hittable<M>,
+    {
+        match child {
+            WideChild::Empty => {}
+            WideChild::Leaf(index) => {
+                let (id, object) = &self.objects[index as usize];
+                let found = object.hit_packet(packet, ray_t);
+                for (lane, hit) in found.into_iter().enumerate() {
+                    if let Some(mut rec) = hit {
+                        rec.object_id = *id;
+                        ray_t[lane].max = rec.t;
+                        hits[lane] = Some(rec);
+                    }
+                }
+            }
+            WideChild::Node(index) => {
+                let node = &self.nodes[index as usize];
+                let inside = node.hit_children_packet(packet, ray_t);
+                for (child, inside) in node.children.into_iter().zip(inside) {
+                    if !inside.contains(&true) {
+                        continue;
+                    }
+                    let outer = *ray_t;
+                    for (interval, inside) in ray_t.iter_mut().zip(inside) {
+                        if !inside {
+                            *interval = Interval::EMPTY;
+                        }
+                    }
+
+                    self.hit_child_packet(child, packet, ray_t, hits);
+
+                    for (lane, inside) in inside.into_iter().enumerate() {
+                        if !inside {
+                            ray_t[lane] = outer[lane];
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn occluded_child<M>(&self, child: WideChild, ray: &Ray, ray_t: Interval) -> bool
+    where
+        T: Hittable<M>,
+    {
+        match child {
+            WideChild::Empty => false,
+            WideChild::Leaf(index) => self.objects[index as usize].1.occluded(ray, ray_t),
+            WideChild::Node(index) => {
+                let node = &self.nodes[index as usize];
+                let inside = node.hit_children(ray, ray_t);
+                node.children
+                    .into_iter()
+                    .zip(inside)
+                    .any(|(child, inside)| inside && self.occluded_child(child, ray, ray_t))
+            }
+        }
+    }
+
+    /// Tests taken under `child` by `hit_child` for the same query, counting
+    /// one per node as its boxes are tested together.
+    fn child_steps<M>(&self, child: WideChild, ray: &Ray, ray_t: &mut Interval) -> u32
+    where
+        T: Hittable<M>,
+    {
+        match child {
+            WideChild::Empty => 0,
+            WideChild::Leaf(index) => {
+                let object = &self.objects[index as usize].1;
+                let steps = object.traversal_steps(ray, *ray_t);
+                if let Some(rec) = object.hit(ray, *ray_t) {
+                    ray_t.max = rec.t;
+                }
+                steps
+            }
+            WideChild::Node(index) => {
+                let node = &self.nodes[index as usize];
+                let inside = node.hit_children(ray, *ray_t);
+                let mut steps = 1;
+                for (child, inside) in node.children.into_iter().zip(inside) {
+                    if inside {
+                        steps += self.child_steps(child, ray, ray_t);
+                    }
+                }
+                steps
+            }
+        }
+    }
+}
+
+impl<M, T: Hittable<M>> Hittable<M> for WideBVH<T> {
+    fn hit(&self, ray: &Ray, mut ray_t: Interval) -> Option<HitRecord<'_, M>> {
+        let mut hit = None;
+        self.hit_child(self.root, ray, &mut ray_t, &mut hit);
+        hit
+    }
+
+    fn hit_packet(
+        &self,
+        packet: &RayPacket,
+        ray_t: &[Interval; PACKET_WIDTH],
+    ) -> [Option<HitRecord<'_, M>>; PACKET_WIDTH] {
+        let mut hits = Default::default();
+        let mut ray_t = *ray_t;
+        self.hit_child_packet(self.root, packet, &mut ray_t, &mut hits);
+        hits
+    }
+
+    fn occluded(&self, ray: &Ray, ray_t: Interval) -> bool {
+        self.occluded_child(self.root, ray, ray_t)
+    }
+
+    fn bounding_box(&self) -> AABB {
+        self.bbox.clone()
+    }
+
+    fn traversal_steps(&self, ray: &Ray, mut ray_t: Interval) -> u32 {
+        self.child_steps(self.root, ray, &mut ray_t)
+    }
+}
+
+/// Half the surface area of `bbox`, for comparing how likely rays are to
+/// hit boxes.
+fn half_area(bbox: &AABB) -> f64 {
+    let d = bbox.diagonal();
+    d.x * d.y + d.y * d.z + d.z * d.x
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+    use rand_chacha::ChaCha12Rng;
+
+    use super::*;
+    use crate::{sphere::Sphere, vec3::Point};
+
+    fn random_box(rng: &mut impl Rng) -> AABB {
+        // Far from the origin with sizes over several orders of magnitude,
+        // flat boxes included, which is where rounding goes wrong.
+        let corner = Point::random_range(rng, 1e3, 1e3 + 10.0);
+        let size = Point::new(
+            rng.random_range(0.0..1.0) * 10f64.powi(rng.random_range(-6..2)),
+            rng.random_range(0.0..1.0) * 10f64.powi(rng.random_range(-6..2)),
+            if rng.random_bool(0.2) {
+                0.0
+            } else {
+                rng.random_range(0.0..5.0)
+            },
+        );
+        AABB::new_from_points(corner, corner + size)
+    }
+
+    #[test]
+    fn quantized_boxes_never_shrink() {
+        let mut rng = ChaCha12Rng::seed_from_u64(1);
+        for _ in 0..1000 {
+            let boxes: Vec<AABB> = (0..rng.random_range(1..=BVH_WIDTH))
+                .map(|_| random_box(&mut rng))
+                .collect();
+            let node = WideNode::new(&boxes);
+            for (index, bbox) in boxes.iter().enumerate() {
+                let stored = node.child_box(index);
+                for i in 0..3 {
+                    assert!(
+                        stored.axis(i).min <= bbox.axis(i).min,
+                        "{bbox:?} {stored:?}"
+                    );
+                    assert!(
+                        stored.axis(i).max >= bbox.axis(i).max,
+                        "{bbox:?} {stored:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    fn spheres<'a>(rng: &mut impl Rng, material: &'a ()) -> Vec<Sphere<'a, ()>> {
+        (0..200)
+            .map(|_| {
+                let center = Point::random_range(rng, -10.0, 10.0);
+                Sphere::new(center, rng.random_range(0.05..1.0), material)
+            })
+            .collect()
+    }
+
+    fn random_ray(rng: &mut impl Rng) -> Ray {
+        let origin = Point::random_range(rng, -15.0, 15.0);
+        let target = Point::random_range(rng, -10.0, 10.0);
+        Ray::new(origin, target - origin)
+    }
+
+    #[test]
+    fn hits_match_the_binary_tree() {
+        let mut rng = ChaCha12Rng::seed_from_u64(2);
+        let scene = ChaCha12Rng::seed_from_u64(3);
+        let binary = BVHNode::new_from_hittable_list(spheres(&mut scene.clone(), &()));
+        let wide = WideBVH::new_from_hittable_list(spheres(&mut scene.clone(), &()));
+        let ray_t = Interval::new(0.001, f64::INFINITY);
+        let found = |hit: Option<HitRecord<()>>| hit.map(|rec| (rec.t, rec.object_id));
+
+        for _ in 0..1000 {
+            let rays: [Ray; PACKET_WIDTH] = std::array::from_fn(|_| random_ray(&mut rng));
+            // Lanes with an empty interval stand for rays that are done.
+            let intervals: [Interval; PACKET_WIDTH] = std::array::from_fn(|lane| {
+                if lane == 3 && rng.random_bool(0.5) {
+                    Interval::EMPTY
+                } else {
+                    ray_t
+                }
+            });
+            let packet = RayPacket::new(rays.clone());
+            let packet_hits = wide.hit_packet(&packet, &intervals);
+            for (lane, hit) in packet_hits.into_iter().enumerate() {
+                let expected = found(binary.hit(&rays[lane], intervals[lane]));
+                assert_eq!(found(wide.hit(&rays[lane], intervals[lane])), expected);
+                assert_eq!(found(hit), expected);
+                assert_eq!(
+                    wide.occluded(&rays[lane], intervals[lane]),
+                    expected.is_some()
+                );
+            }
+        }
+    }
+}
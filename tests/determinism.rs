@@ -0,0 +1,74 @@
+use ray_tracing_in_one_weekend::{
+    camera::{Camera, CameraBuilder},
+    color::Color,
+    film::Pass,
+    filter::Filter,
+    material::{Dielectric, Lambertian, MaterialKind, Metal},
+    sphere::Sphere,
+    vec3::{Point, Vec3},
+    wide_bvh::WideBVH,
+};
+
+fn camera() -> Camera {
+    // Several tiles, with a filter reaching across their edges
+    CameraBuilder::new()
+        .image_width(48)
+        .aspect_ratio(16.0 / 9.0)
+        .samples_per_pixel(8)
+        .samples_per_pass(4)
+        .vfov(30.0)
+        .lookfrom(Point::new(0.0, 1.5, 6.0))
+        .lookat(Point::new(0.0, 0.5, 0.0))
+        .vup(Vec3::new(0.0, 1.0, 0.0))
+        .defocus_angle(1.0)
+        .focus_dist(6.0)
+        .filter(Filter::Gaussian {
+            radius: 1.5,
+            sigma: 0.5,
+        })
+        .build()
+}
+
+/// Beauty pass of a small scene with every kind of material and a moving
+/// sphere, rendered with `seed`.
+fn render(seed: u64) -> Vec<f64> {
+    let ground = MaterialKind::Lambertian(Lambertian::new(Color::new(0.5, 0.5, 0.5)));
+    let glass = MaterialKind::Dielectric(Dielectric::new(1.5));
+    let metal = MaterialKind::Metal(Metal::new(Color::new(0.7, 0.6, 0.5), 0.3));
+    let diffuse = MaterialKind::Lambertian(Lambertian::new(Color::new(0.4, 0.2, 0.1)));
+    let world = WideBVH::new_from_hittable_list(vec![
+        Sphere::new(Point::new(0.0, -1000.0, 0.0), 1000.0, &ground),
+        Sphere::new(Point::new(0.0, 1.0, 0.0), 1.0, &glass),
+        Sphere::new(Point::new(-2.0, 1.0, 0.0), 1.0, &metal),
+        Sphere::new_moving(
+            Point::new(2.0, 0.5, 0.5),
+            Point::new(2.0, 0.8, 0.5),
+            0.5,
+            &diffuse,
+        ),
+    ]);
+    camera().render(seed, &world).pass(Pass::Beauty)
+}
+
+fn render_with_threads(seed: u64, threads: usize) -> Vec<f64> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .expect("thread pool")
+        .install(|| render(seed))
+}
+
+#[test]
+fn same_seed_gives_the_same_image() {
+    let image = render(7);
+    assert_eq!(image, render(7));
+    assert_ne!(image, render(8));
+}
+
+#[test]
+fn thread_count_does_not_change_the_image() {
+    let image = render_with_threads(7, 1);
+    for threads in [2, 5] {
+        assert_eq!(image, render_with_threads(7, threads), "{threads} threads");
+    }
+}